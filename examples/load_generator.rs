@@ -0,0 +1,158 @@
+//! Simple UDP load generator for measuring query throughput.
+//!
+//! Starts an in-process server answering from a static mapping and sends queries to it
+//! from many concurrent clients, each waiting for its answer before sending the next query.
+//! The server and the clients run on separate runtimes with their own worker threads:
+//!
+//!     cargo run --release --example load_generator -- --udp-sockets 4 --server-threads 4 --client-threads 4
+//!
+//! With `--target` it loads an already running server instead, e.g. a `docker-dns` process
+//! pinned to other cores with `taskset`, so that the clients don't compete with it at all.
+
+use clap::Parser;
+use docker_dns::custom_handler::CustomHandlerConfig;
+use docker_dns::resolver::StaticResolver;
+use docker_dns::server::DnsServer;
+use hickory_client::op::{Message, Query};
+use hickory_client::proto::serialize::binary::BinEncodable;
+use hickory_client::rr::{Name, RecordType};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Query an already running server instead of an in-process one
+    #[arg(long)]
+    target: Option<SocketAddr>,
+
+    /// Address of the in-process server
+    #[arg(long, default_value = "127.0.0.1:5399")]
+    bind: SocketAddr,
+
+    /// UDP sockets (SO_REUSEPORT) of the in-process server
+    #[arg(long, default_value = "1")]
+    udp_sockets: usize,
+
+    /// Worker threads of the in-process server's runtime
+    #[arg(long, default_value = "1")]
+    server_threads: usize,
+
+    /// Worker threads of the clients' runtime
+    #[arg(long, default_value = "1")]
+    client_threads: usize,
+
+    /// Concurrent clients
+    #[arg(long, default_value = "64")]
+    clients: usize,
+
+    /// Test duration in seconds
+    #[arg(long, default_value = "10")]
+    duration: u64,
+
+    /// Name to query
+    #[arg(long, default_value = "web")]
+    name: String,
+}
+
+#[derive(Default)]
+struct ClientStats {
+    answered: u64,
+    timed_out: u64,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let cores = std::thread::available_parallelism().map_or(1, usize::from);
+
+    // Kept alive until the clients are done
+    let server_runtime = runtime(args.server_threads)?;
+    let target = match args.target {
+        Some(target) => target,
+        None => server_runtime.block_on(start_server(&args))?,
+    };
+
+    let total = runtime(args.client_threads)?.block_on(run_clients(&args, target))?;
+
+    match args.target {
+        Some(target) => println!("{} cores, server {}", cores, target),
+        None => println!(
+            "{} cores, server: {} threads, {} UDP sockets",
+            cores, args.server_threads, args.udp_sockets
+        ),
+    }
+    println!(
+        "{} clients on {} threads, {} s: {} answered ({:.0} queries/s), {} timed out",
+        args.clients,
+        args.client_threads,
+        args.duration,
+        total.answered,
+        total.answered as f64 / args.duration as f64,
+        total.timed_out
+    );
+    Ok(())
+}
+
+/// Starts the in-process server on the current runtime, where it keeps running
+async fn start_server(args: &Args) -> anyhow::Result<SocketAddr> {
+    let mut resolver = StaticResolver::new();
+    resolver.add_mapping(args.name.clone(), Ipv4Addr::new(172, 17, 0, 2));
+    let server = DnsServer::new(Arc::new(resolver), args.bind, CustomHandlerConfig::default())
+        .with_udp_sockets(args.udp_sockets)
+        .start();
+    server.ready().await?;
+    Ok(server.local_addrs()[0])
+}
+
+fn runtime(worker_threads: usize) -> std::io::Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads.max(1))
+        .enable_all()
+        .build()
+}
+
+async fn run_clients(args: &Args, target: SocketAddr) -> anyhow::Result<ClientStats> {
+    let query = query_bytes(&args.name)?;
+    let deadline = Instant::now() + Duration::from_secs(args.duration);
+
+    let clients = (0..args.clients)
+        .map(|_| tokio::spawn(run_client(target, query.clone(), deadline)))
+        .collect::<Vec<_>>();
+
+    let mut total = ClientStats::default();
+    for client in clients {
+        let stats = client.await??;
+        total.answered += stats.answered;
+        total.timed_out += stats.timed_out;
+    }
+    Ok(total)
+}
+
+fn query_bytes(name: &str) -> anyhow::Result<Vec<u8>> {
+    let mut message = Message::new();
+    message.set_recursion_desired(true);
+    message.add_query(Query::query(Name::from_str(name)?, RecordType::A));
+    Ok(message.to_bytes()?)
+}
+
+async fn run_client(target: SocketAddr, query: Vec<u8>, deadline: Instant) -> anyhow::Result<ClientStats> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    socket.connect(target).await?;
+
+    let mut stats = ClientStats::default();
+    let mut buffer = [0u8; 512];
+    while Instant::now() < deadline {
+        socket.send(&query).await?;
+        match tokio::time::timeout(Duration::from_secs(1), socket.recv(&mut buffer)).await {
+            Ok(received) => {
+                received?;
+                stats.answered += 1;
+            }
+            Err(_) => stats.timed_out += 1,
+        }
+    }
+
+    Ok(stats)
+}
@@ -0,0 +1,183 @@
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Client CIDR rules for a zone, e.g. "internal.docker"
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneRule {
+    /// Lowercase domain without trailing dot. The rule covers the domain and its subdomains.
+    pub zone: String,
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+/// Which clients may query which names.
+///
+/// A client is denied if it matches a deny entry, or if there are allow entries
+/// and it matches none of them. The global rules and the rule of the most specific
+/// matching zone must both allow the client.
+#[derive(Debug, Clone, Default)]
+pub struct AccessControl {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+    pub zones: Vec<ZoneRule>,
+    /// Silently drop denied queries instead of answering REFUSED
+    pub drop_denied: bool,
+}
+
+impl AccessControl {
+    /// `domain` is the normalized query name (lowercase, no trailing dot)
+    pub fn is_allowed(&self, client: IpAddr, domain: &str) -> bool {
+        if !is_allowed_by(&self.allow, &self.deny, client) {
+            return false;
+        }
+
+        match self.zone_rule(domain) {
+            Some(rule) => is_allowed_by(&rule.allow, &rule.deny, client),
+            None => true,
+        }
+    }
+
+    fn zone_rule(&self, domain: &str) -> Option<&ZoneRule> {
+        self.zones
+            .iter()
+            .filter(|rule| {
+                domain == rule.zone
+                    || (domain.ends_with(&rule.zone) && domain[..domain.len() - rule.zone.len()].ends_with('.'))
+            })
+            .max_by_key(|rule| rule.zone.len())
+    }
+
+    /// Adds a client subnet to the allow or deny list of a zone
+    pub fn add_zone_entry(&mut self, entry: &ZoneEntry, allow: bool) {
+        let zone = entry.zone.clone();
+        let rule = match self.zones.iter_mut().position(|rule| rule.zone == zone) {
+            Some(index) => &mut self.zones[index],
+            None => {
+                self.zones.push(ZoneRule {
+                    zone,
+                    allow: vec![],
+                    deny: vec![],
+                });
+                self.zones.last_mut().unwrap()
+            }
+        };
+
+        if allow {
+            rule.allow.push(entry.subnet);
+        } else {
+            rule.deny.push(entry.subnet);
+        }
+    }
+}
+
+fn is_allowed_by(allow: &[IpNet], deny: &[IpNet], client: IpAddr) -> bool {
+    if deny.iter().any(|subnet| subnet.contains(&client)) {
+        return false;
+    }
+
+    allow.is_empty() || allow.iter().any(|subnet| subnet.contains(&client))
+}
+
+/// A zone and client subnet, parsed from "<zone>=<cidr>"
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneEntry {
+    pub zone: String,
+    pub subnet: IpNet,
+}
+
+impl FromStr for ZoneEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (zone, subnet) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid zone rule '{}' (expected <zone>=<cidr>)", s))?;
+        let zone = zone.trim().trim_matches('.').to_ascii_lowercase();
+        if zone.is_empty() {
+            return Err(format!("invalid zone rule '{}' (zone must not be empty)", s));
+        }
+        let subnet = subnet
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid zone rule '{}': {}", s, e))?;
+
+        Ok(Self { zone, subnet })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net(s: &str) -> IpNet {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn allows_everyone_by_default() {
+        let acl = AccessControl::default();
+
+        assert!(acl.is_allowed(ip("192.168.1.10"), "web.docker"));
+        assert!(acl.is_allowed(ip("::1"), "web.docker"));
+    }
+
+    #[test]
+    fn applies_allow_and_deny_lists() {
+        let acl = AccessControl {
+            allow: vec![net("127.0.0.0/8"), net("172.16.0.0/12")],
+            deny: vec![net("172.19.0.0/16")],
+            ..Default::default()
+        };
+
+        assert!(acl.is_allowed(ip("127.0.0.1"), "web.docker"));
+        assert!(acl.is_allowed(ip("172.17.0.5"), "web.docker"));
+        assert!(!acl.is_allowed(ip("172.19.0.5"), "web.docker"));
+        assert!(!acl.is_allowed(ip("192.168.1.10"), "web.docker"));
+    }
+
+    #[test]
+    fn applies_most_specific_zone_rule() {
+        let mut acl = AccessControl::default();
+        acl.add_zone_entry(&"internal.docker=127.0.0.0/8".parse().unwrap(), true);
+        acl.add_zone_entry(&"db.internal.docker=10.0.0.0/8".parse().unwrap(), true);
+
+        assert!(acl.is_allowed(ip("127.0.0.1"), "internal.docker"));
+        assert!(acl.is_allowed(ip("127.0.0.1"), "api.internal.docker"));
+        assert!(!acl.is_allowed(ip("10.0.0.1"), "api.internal.docker"));
+        assert!(acl.is_allowed(ip("10.0.0.1"), "db.internal.docker"));
+        assert!(!acl.is_allowed(ip("127.0.0.1"), "db.internal.docker"));
+        // Not a subdomain, only the same ending
+        assert!(acl.is_allowed(ip("10.0.0.1"), "notinternal.docker"));
+    }
+
+    #[test]
+    fn global_deny_applies_inside_zones() {
+        let mut acl = AccessControl {
+            deny: vec![net("10.0.0.66/32")],
+            ..Default::default()
+        };
+        acl.add_zone_entry(&"internal.docker=10.0.0.0/8".parse().unwrap(), true);
+
+        assert!(acl.is_allowed(ip("10.0.0.1"), "api.internal.docker"));
+        assert!(!acl.is_allowed(ip("10.0.0.66"), "api.internal.docker"));
+    }
+
+    #[test]
+    fn parses_zone_entries() {
+        assert_eq!(
+            "Internal.Docker.=10.0.0.0/8".parse::<ZoneEntry>(),
+            Ok(ZoneEntry {
+                zone: "internal.docker".to_string(),
+                subnet: net("10.0.0.0/8"),
+            })
+        );
+        assert!("internal.docker".parse::<ZoneEntry>().is_err());
+        assert!("=10.0.0.0/8".parse::<ZoneEntry>().is_err());
+        assert!("internal.docker=nonsense".parse::<ZoneEntry>().is_err());
+    }
+}
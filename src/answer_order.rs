@@ -0,0 +1,230 @@
+use crate::resolver::DnsResponse;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Order in which the addresses of a name are returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnswerOrder {
+    /// Always the same order
+    #[default]
+    Fixed,
+    /// Rotate the addresses by one position on every query
+    RoundRobin,
+    /// Shuffle the addresses on every query
+    Random,
+    /// Shuffle the addresses so that the chance of coming first is proportional to their weight
+    Weighted,
+}
+
+impl FromStr for AnswerOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fixed" => Ok(Self::Fixed),
+            "round-robin" => Ok(Self::RoundRobin),
+            "random" => Ok(Self::Random),
+            "weighted" => Ok(Self::Weighted),
+            _ => Err(format!(
+                "invalid answer order '{}' (expected fixed, round-robin, random or weighted)",
+                s
+            )),
+        }
+    }
+}
+
+/// Names whose round-robin position is kept. Past this, the positions start over.
+const MAX_ROTATIONS: usize = 10_000;
+
+/// Applies an answer order, keeping the round-robin position of each name between queries.
+///
+/// Addresses are answered by preference first (split horizon "prefer", health "prefer-healthy",
+/// reachability "deprioritize" and preferred networks, in that order of significance). The answer
+/// order only reorders addresses of equal preference, so it spreads load without overriding them.
+pub struct AnswerOrderer {
+    order: AnswerOrder,
+    /// Next rotation by name and address family
+    rotations: Mutex<HashMap<(String, bool), usize>>,
+}
+
+impl AnswerOrderer {
+    pub fn new(order: AnswerOrder) -> Self {
+        Self {
+            order,
+            rotations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the addresses of `name` in answer order. Preferences and weights are looked up in the response.
+    pub fn order<T: Copy + Into<IpAddr>>(&self, name: &str, addresses: &[T], response: &DnsResponse) -> Vec<T> {
+        let mut ordered = addresses.to_vec();
+        if ordered.len() < 2 {
+            return ordered;
+        }
+
+        // Stable, so addresses of equal preference keep their resolved order
+        let preference = |ip: &T| response.preference((*ip).into());
+        ordered.sort_by_key(preference);
+
+        let rotation = match self.order {
+            AnswerOrder::RoundRobin => self.next_rotation(name, ordered[0].into().is_ipv4()),
+            _ => 0,
+        };
+        for group in ordered.chunk_by_mut(|a, b| preference(a) == preference(b)) {
+            match self.order {
+                AnswerOrder::Fixed => {}
+                AnswerOrder::RoundRobin => group.rotate_left(rotation % group.len()),
+                AnswerOrder::Random => group.shuffle(&mut rand::thread_rng()),
+                AnswerOrder::Weighted => order_by_weight(group, response),
+            }
+        }
+
+        ordered
+    }
+
+    fn next_rotation(&self, name: &str, ipv4: bool) -> usize {
+        let key = (name.to_ascii_lowercase(), ipv4);
+        let mut rotations = self.rotations.lock().unwrap_or_else(|e| e.into_inner());
+        if rotations.len() >= MAX_ROTATIONS && !rotations.contains_key(&key) {
+            rotations.clear();
+        }
+        let rotation = rotations.entry(key).or_default();
+        let current = *rotation;
+        *rotation = rotation.wrapping_add(1);
+        current
+    }
+}
+
+/// Weighted random sampling without replacement (Efraimidis-Spirakis):
+/// sort by u^(1/weight) with u uniform in (0, 1]. Weight 0 always goes last.
+fn order_by_weight<T: Copy + Into<IpAddr>>(addresses: &mut [T], response: &DnsResponse) {
+    let mut rng = rand::thread_rng();
+    let mut keyed = addresses
+        .iter()
+        .map(|ip| {
+            let weight = response.weight((*ip).into());
+            let key = if weight == 0 {
+                -1.0
+            } else {
+                (1.0 - rng.r#gen::<f64>()).powf(1.0 / f64::from(weight))
+            };
+            (key, *ip)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    for (slot, (_, ip)) in addresses.iter_mut().zip(keyed) {
+        *slot = ip;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::Preference;
+    use std::collections::HashSet;
+    use std::net::Ipv4Addr;
+
+    fn addresses(count: u8) -> Vec<Ipv4Addr> {
+        (1..=count).map(|i| Ipv4Addr::new(172, 17, 0, i)).collect()
+    }
+
+    #[test]
+    fn fixed_order_keeps_addresses() {
+        let orderer = AnswerOrderer::new(AnswerOrder::Fixed);
+        let response = DnsResponse::new(addresses(3), vec![]);
+
+        assert_eq!(orderer.order("web", &response.ipv4_addresses, &response), addresses(3));
+        assert_eq!(orderer.order("web", &response.ipv4_addresses, &response), addresses(3));
+    }
+
+    #[test]
+    fn round_robin_rotates_on_every_query() {
+        let orderer = AnswerOrderer::new(AnswerOrder::RoundRobin);
+        let response = DnsResponse::new(addresses(3), vec![]);
+
+        let firsts = (0..4)
+            .map(|_| orderer.order("web", &response.ipv4_addresses, &response)[0])
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            firsts,
+            vec![
+                Ipv4Addr::new(172, 17, 0, 1),
+                Ipv4Addr::new(172, 17, 0, 2),
+                Ipv4Addr::new(172, 17, 0, 3),
+                Ipv4Addr::new(172, 17, 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn random_order_is_a_permutation() {
+        let orderer = AnswerOrderer::new(AnswerOrder::Random);
+        let response = DnsResponse::new(addresses(10), vec![]);
+
+        let mut ordered = orderer.order("web", &response.ipv4_addresses, &response);
+        ordered.sort();
+
+        assert_eq!(ordered, addresses(10));
+    }
+
+    #[test]
+    fn weighted_order_puts_zero_weight_last() {
+        let orderer = AnswerOrderer::new(AnswerOrder::Weighted);
+        let mut response = DnsResponse::new(addresses(3), vec![]);
+        response.weights = HashMap::from([(IpAddr::from([172, 17, 0, 1]), 0)]);
+
+        for _ in 0..20 {
+            let ordered = orderer.order("web", &response.ipv4_addresses, &response);
+            assert_eq!(ordered[2], Ipv4Addr::new(172, 17, 0, 1));
+        }
+    }
+
+    #[test]
+    fn round_robin_keeps_a_position_per_name() {
+        let orderer = AnswerOrderer::new(AnswerOrder::RoundRobin);
+        let response = DnsResponse::new(addresses(3), vec![]);
+
+        assert_eq!(orderer.order("web", &response.ipv4_addresses, &response)[0], Ipv4Addr::new(172, 17, 0, 1));
+        assert_eq!(orderer.order("web", &response.ipv4_addresses, &response)[0], Ipv4Addr::new(172, 17, 0, 2));
+        assert_eq!(orderer.order("db", &response.ipv4_addresses, &response)[0], Ipv4Addr::new(172, 17, 0, 1));
+        assert_eq!(orderer.order("WEB", &response.ipv4_addresses, &response)[0], Ipv4Addr::new(172, 17, 0, 3));
+    }
+
+    #[test]
+    fn orders_only_within_preference_groups() {
+        let mut response = DnsResponse::new(addresses(4), vec![]);
+        let remote = Preference {
+            remote: true,
+            ..Default::default()
+        };
+        // Resolved with the local addresses .2 and .4 first
+        response.ipv4_addresses = [2, 4, 1, 3].map(|i| Ipv4Addr::new(172, 17, 0, i)).to_vec();
+        response.preferences = HashMap::from([(IpAddr::from([172, 17, 0, 1]), remote), (IpAddr::from([172, 17, 0, 3]), remote)]);
+        let preferred = [Ipv4Addr::new(172, 17, 0, 2), Ipv4Addr::new(172, 17, 0, 4)];
+
+        for order in [AnswerOrder::RoundRobin, AnswerOrder::Random, AnswerOrder::Weighted] {
+            let orderer = AnswerOrderer::new(order);
+            let mut firsts = HashSet::new();
+            for _ in 0..50 {
+                let ordered = orderer.order("web", &response.ipv4_addresses, &response);
+                assert!(preferred.contains(&ordered[0]) && preferred.contains(&ordered[1]), "{:?}: {:?}", order, ordered);
+                firsts.insert(ordered[0]);
+            }
+            assert_eq!(firsts.len(), 2, "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn parses_answer_orders() {
+        assert_eq!("fixed".parse::<AnswerOrder>(), Ok(AnswerOrder::Fixed));
+        assert_eq!("Round-Robin".parse::<AnswerOrder>(), Ok(AnswerOrder::RoundRobin));
+        assert_eq!("random".parse::<AnswerOrder>(), Ok(AnswerOrder::Random));
+        assert_eq!("weighted".parse::<AnswerOrder>(), Ok(AnswerOrder::Weighted));
+        assert!("sorted".parse::<AnswerOrder>().is_err());
+    }
+}
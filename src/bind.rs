@@ -0,0 +1,162 @@
+use anyhow::{Context, Result};
+use nix::ifaddrs::getifaddrs;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+use std::str::FromStr;
+
+/// Where to listen: a socket address, or all current addresses of a network interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindAddress {
+    Socket(SocketAddr),
+    /// e.g. "docker0:53"
+    Interface { name: String, port: u16 },
+}
+
+impl BindAddress {
+    pub fn is_interface(&self) -> bool {
+        matches!(self, Self::Interface { .. })
+    }
+
+    /// The socket addresses to listen on, given the addresses of the host's interfaces
+    pub fn resolve(&self, interfaces: &[InterfaceAddress]) -> Vec<SocketAddr> {
+        match self {
+            Self::Socket(addr) => vec![*addr],
+            Self::Interface { name, port } => interfaces
+                .iter()
+                .filter(|interface| interface.name == *name)
+                .map(|interface| match interface.ip {
+                    // Link-local addresses are only usable together with their interface
+                    IpAddr::V6(ip) if interface.scope_id != 0 => {
+                        SocketAddr::V6(SocketAddrV6::new(ip, *port, 0, interface.scope_id))
+                    }
+                    ip => SocketAddr::new(ip, *port),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for BindAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self::Socket(addr));
+        }
+
+        let invalid = || format!("invalid bind address '{}' (expected <ip>:<port> or <interface>:<port>)", s);
+        let (name, port) = s.rsplit_once(':').ok_or_else(invalid)?;
+        let port = port.parse::<u16>().map_err(|_| invalid())?;
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ':' || c == '/') {
+            return Err(invalid());
+        }
+
+        Ok(Self::Interface {
+            name: name.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Socket(addr) => write!(f, "{}", addr),
+            Self::Interface { name, port } => write!(f, "{}:{}", name, port),
+        }
+    }
+}
+
+/// An IP address assigned to a network interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    pub name: String,
+    pub ip: IpAddr,
+    /// IPv6 scope (the interface index for link-local addresses, otherwise 0)
+    pub scope_id: u32,
+}
+
+/// Lists the IP addresses of the host's network interfaces
+pub fn interface_addresses() -> Result<Vec<InterfaceAddress>> {
+    let addresses = getifaddrs()
+        .context("Failed to list network interfaces")?
+        .filter_map(|interface| {
+            let address = interface.address?;
+            let (ip, scope_id) = if let Some(ipv4) = address.as_sockaddr_in() {
+                (IpAddr::V4(ipv4.ip()), 0)
+            } else {
+                let ipv6 = address.as_sockaddr_in6()?;
+                (IpAddr::V6(ipv6.ip()), ipv6.scope_id())
+            };
+            Some(InterfaceAddress {
+                name: interface.interface_name,
+                ip,
+                scope_id,
+            })
+        })
+        .collect();
+
+    Ok(addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(name: &str, ip: &str, scope_id: u32) -> InterfaceAddress {
+        InterfaceAddress {
+            name: name.to_string(),
+            ip: ip.parse().unwrap(),
+            scope_id,
+        }
+    }
+
+    #[test]
+    fn parses_socket_and_interface_addresses() {
+        assert_eq!(
+            "127.0.0.1:53".parse::<BindAddress>(),
+            Ok(BindAddress::Socket("127.0.0.1:53".parse().unwrap()))
+        );
+        assert_eq!(
+            "[::1]:53".parse::<BindAddress>(),
+            Ok(BindAddress::Socket("[::1]:53".parse().unwrap()))
+        );
+        assert_eq!(
+            "docker0:53".parse::<BindAddress>(),
+            Ok(BindAddress::Interface {
+                name: "docker0".to_string(),
+                port: 53
+            })
+        );
+        assert!("docker0".parse::<BindAddress>().is_err());
+        assert!(":53".parse::<BindAddress>().is_err());
+        assert!("docker0:dns".parse::<BindAddress>().is_err());
+    }
+
+    #[test]
+    fn resolves_interface_to_its_addresses() {
+        let interfaces = vec![
+            interface("lo", "127.0.0.1", 0),
+            interface("docker0", "172.17.0.1", 0),
+            interface("docker0", "fd00::1", 0),
+            interface("docker0", "fe80::1", 3),
+        ];
+        let bind = "docker0:53".parse::<BindAddress>().unwrap();
+
+        assert_eq!(
+            bind.resolve(&interfaces),
+            vec![
+                "172.17.0.1:53".parse::<SocketAddr>().unwrap(),
+                "[fd00::1]:53".parse().unwrap(),
+                SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 53, 0, 3)),
+            ]
+        );
+        assert!("br-missing:53".parse::<BindAddress>().unwrap().resolve(&interfaces).is_empty());
+    }
+
+    #[test]
+    fn lists_loopback_interface() {
+        let addresses = interface_addresses().unwrap();
+        assert!(addresses.iter().any(|address| address.ip == IpAddr::from([127, 0, 0, 1])));
+    }
+}
@@ -0,0 +1,185 @@
+use rand::Rng;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// When to stop calling a failing dependency, and how long to wait before trying again
+#[derive(Debug, Clone)]
+pub struct BackoffConfig {
+    /// Consecutive failures that open the circuit
+    pub failure_threshold: u32,
+    /// Pause after the circuit opens. Doubles with every failed probe, up to `max_delay`.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CircuitState {
+    /// Calls go through
+    #[default]
+    Closed,
+    /// Calls are skipped until the pause is over
+    Open,
+    /// The pause is over; the next call probes whether the dependency is back
+    HalfOpen,
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed => write!(f, "closed"),
+            Self::Open => write!(f, "open"),
+            Self::HalfOpen => write!(f, "half-open"),
+        }
+    }
+}
+
+/// What a failure did to the circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureOutcome {
+    /// Still below the threshold
+    Closed,
+    /// The circuit opened for this long
+    Opened(Duration),
+    /// A probe failed and the circuit opened again for this long
+    Reopened(Duration),
+}
+
+/// Circuit breaker with exponential backoff and jitter
+pub struct CircuitBreaker {
+    config: BackoffConfig,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    circuit: CircuitState,
+    consecutive_failures: u32,
+    /// Times opened since the last success, for the backoff
+    openings: u32,
+    retry_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BackoffConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.lock().circuit
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.lock().consecutive_failures
+    }
+
+    /// Whether a call may go through now. Moves an open circuit whose pause is over to half-open.
+    pub fn allow(&self) -> bool {
+        let mut state = self.lock();
+        if state.circuit == CircuitState::Open && state.retry_at.is_none_or(|retry_at| Instant::now() >= retry_at) {
+            state.circuit = CircuitState::HalfOpen;
+        }
+        state.circuit != CircuitState::Open
+    }
+
+    /// Closes the circuit. Returns the number of failures before, if there were any.
+    pub fn record_success(&self) -> Option<u32> {
+        let mut state = self.lock();
+        let failures = state.consecutive_failures;
+        *state = BreakerState::default();
+        (failures > 0).then_some(failures)
+    }
+
+    pub fn record_failure(&self) -> FailureOutcome {
+        let mut state = self.lock();
+        state.consecutive_failures += 1;
+
+        let was_closed = state.circuit == CircuitState::Closed;
+        if was_closed && state.consecutive_failures < self.config.failure_threshold {
+            return FailureOutcome::Closed;
+        }
+
+        state.openings += 1;
+        let delay = self.delay(state.openings);
+        state.circuit = CircuitState::Open;
+        state.retry_at = Some(Instant::now() + delay);
+        if was_closed {
+            FailureOutcome::Opened(delay)
+        } else {
+            FailureOutcome::Reopened(delay)
+        }
+    }
+
+    /// The initial delay doubled for every opening, capped, with up to half of it randomized
+    /// so that several instances don't retry in lockstep
+    fn delay(&self, openings: u32) -> Duration {
+        let factor = 2u32.saturating_pow(openings.saturating_sub(1));
+        let delay = self.config.initial_delay.saturating_mul(factor).min(self.config.max_delay);
+        let half = delay / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(initial_delay: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(BackoffConfig {
+            failure_threshold: 2,
+            initial_delay,
+            max_delay: Duration::from_secs(8),
+        })
+    }
+
+    #[test]
+    fn opens_after_threshold_and_closes_after_successful_probe() {
+        let breaker = breaker(Duration::from_millis(20));
+
+        assert_eq!(breaker.record_failure(), FailureOutcome::Closed);
+        assert!(breaker.allow());
+        assert!(matches!(breaker.record_failure(), FailureOutcome::Opened(_)));
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(matches!(breaker.record_failure(), FailureOutcome::Reopened(_)));
+        assert!(!breaker.allow());
+
+        std::thread::sleep(Duration::from_millis(45));
+        assert!(breaker.allow());
+        assert_eq!(breaker.record_success(), Some(3));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(breaker.record_success(), None);
+    }
+
+    #[test]
+    fn backs_off_exponentially_with_jitter_up_to_the_maximum() {
+        let breaker = breaker(Duration::from_secs(1));
+
+        for (openings, full_delay) in [(1, 1), (2, 2), (3, 4), (4, 8), (5, 8), (30, 8)] {
+            let full_delay = Duration::from_secs(full_delay);
+            let delay = breaker.delay(openings);
+            assert!(delay >= full_delay / 2 && delay <= full_delay, "{:?} for {} openings", delay, openings);
+        }
+    }
+}
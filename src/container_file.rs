@@ -0,0 +1,310 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bollard::secret::{
+    ContainerInspectResponse, ContainerSummary, ContainerSummaryNetworkSettings, EndpointSettings,
+};
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use crate::docker_client::{DockerClientConfig, LabelWarnings, NetworkInfo, NetworkInfoProvider, network_infos};
+
+/// Reads the containers from a file instead of Docker, e.g. to run without a Docker daemon or
+/// to reproduce an issue from captured data. The file is JSON holding one of:
+/// - the output of `docker inspect` (stopped containers are skipped)
+/// - the Docker API container list (`GET /containers/json`)
+/// - the format below, with addresses given as "<ip>" or "<ip>/<prefix length>":
+///
+/// ```json
+/// {
+///   "containers": [{
+///     "name": "web",
+///     "labels": { "com.docker.compose.service": "frontend" },
+///     "health": "healthy",
+///     "networks": { "bridge": { "ipv4": "172.17.0.2/16", "ipv6": "fd00::2/64" } }
+///   }],
+///   "internal_networks": ["backend"]
+/// }
+/// ```
+///
+/// The file is read on every refresh. Only the naming and network options of the config apply.
+pub struct ContainerFile {
+    path: PathBuf,
+    config: DockerClientConfig,
+    label_warnings: LabelWarnings,
+}
+
+impl ContainerFile {
+    pub fn new(path: impl Into<PathBuf>, config: DockerClientConfig) -> Self {
+        Self {
+            path: path.into(),
+            config,
+            label_warnings: LabelWarnings::default(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[async_trait]
+impl NetworkInfoProvider for ContainerFile {
+    async fn list_containers_network_info(&self) -> Result<Vec<NetworkInfo>> {
+        let content = tokio::fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        let (containers, internal_networks) =
+            parse_containers(&content).with_context(|| format!("Invalid container file {}", self.path.display()))?;
+
+        Ok(network_infos(&containers, &internal_networks, &self.config, &self.label_warnings))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OwnFormat {
+    containers: Vec<OwnContainer>,
+    #[serde(default)]
+    internal_networks: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OwnContainer {
+    name: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    /// "starting", "healthy" or "unhealthy"
+    health: Option<String>,
+    #[serde(default)]
+    networks: HashMap<String, OwnEndpoint>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OwnEndpoint {
+    ipv4: Option<String>,
+    ipv6: Option<String>,
+}
+
+/// Returns the running containers and the IDs of internal networks
+fn parse_containers(content: &str) -> Result<(Vec<ContainerSummary>, HashSet<String>)> {
+    // `docker ps --format json` prints one object per line, without addresses
+    if let Some(first_line) = content.lines().next()
+        && let Ok(Value::Object(object)) = serde_json::from_str::<Value>(first_line)
+        && object.get("Names").is_some_and(Value::is_string)
+    {
+        anyhow::bail!("`docker ps` output has no addresses; use the output of `docker inspect $(docker ps -q)` instead");
+    }
+
+    let value: Value = serde_json::from_str(content)?;
+    let items = match &value {
+        Value::Object(object) if object.contains_key("containers") => {
+            let own = serde_json::from_value::<OwnFormat>(value)?;
+            return from_own_format(own);
+        }
+        Value::Array(items) => items,
+        _ => anyhow::bail!("expected a list of containers or an object with 'containers'"),
+    };
+
+    let containers = if items.iter().all(|item| item.get("Names").is_some()) {
+        serde_json::from_value::<Vec<ContainerSummary>>(value)?
+    } else if items.iter().all(|item| item.get("NetworkSettings").is_some()) {
+        serde_json::from_value::<Vec<ContainerInspectResponse>>(value)?
+            .into_iter()
+            .filter_map(from_inspect)
+            .collect()
+    } else {
+        anyhow::bail!("expected `docker inspect` output or the Docker API container list");
+    };
+
+    Ok((containers, HashSet::new()))
+}
+
+fn from_inspect(container: ContainerInspectResponse) -> Option<ContainerSummary> {
+    let state = container.state.unwrap_or_default();
+    if state.running == Some(false) {
+        return None;
+    }
+
+    let health = state.health.and_then(|health| health.status).map(|status| status.to_string());
+    Some(ContainerSummary {
+        id: container.id,
+        names: container.name.map(|name| vec![name]),
+        labels: container.config.and_then(|config| config.labels),
+        status: Some(status_with_health(health.as_deref())),
+        network_settings: Some(ContainerSummaryNetworkSettings {
+            networks: container.network_settings.and_then(|settings| settings.networks),
+        }),
+        ..Default::default()
+    })
+}
+
+fn from_own_format(own: OwnFormat) -> Result<(Vec<ContainerSummary>, HashSet<String>)> {
+    let mut containers = vec![];
+    for container in own.containers {
+        let mut networks = HashMap::new();
+        for (network, endpoint) in container.networks {
+            let context = || format!("container '{}', network '{}'", container.name, network);
+            let (ipv4, ipv4_prefix_len) = parse_address(endpoint.ipv4.as_deref()).with_context(context)?;
+            let (ipv6, ipv6_prefix_len) = parse_address(endpoint.ipv6.as_deref()).with_context(context)?;
+            networks.insert(
+                network.clone(),
+                EndpointSettings {
+                    // Internal networks are listed by name
+                    network_id: Some(network),
+                    ip_address: ipv4,
+                    ip_prefix_len: ipv4_prefix_len,
+                    global_ipv6_address: ipv6,
+                    global_ipv6_prefix_len: ipv6_prefix_len,
+                    ..Default::default()
+                },
+            );
+        }
+
+        containers.push(ContainerSummary {
+            names: Some(vec![container.name]),
+            labels: Some(container.labels),
+            status: Some(status_with_health(container.health.as_deref())),
+            network_settings: Some(ContainerSummaryNetworkSettings { networks: Some(networks) }),
+            ..Default::default()
+        });
+    }
+
+    Ok((containers, own.internal_networks.into_iter().collect()))
+}
+
+/// Splits "<ip>/<prefix length>" as Docker reports it
+fn parse_address(address: Option<&str>) -> Result<(Option<String>, Option<i64>)> {
+    let Some(address) = address else {
+        return Ok((None, None));
+    };
+
+    if let Ok(subnet) = address.parse::<IpNet>() {
+        return Ok((Some(subnet.addr().to_string()), Some(i64::from(subnet.prefix_len()))));
+    }
+    let ip = address
+        .parse::<IpAddr>()
+        .map_err(|_| anyhow::anyhow!("invalid address '{}'", address))?;
+    Ok((Some(ip.to_string()), None))
+}
+
+/// A container status as Docker lists it, which is where the health is read from
+fn status_with_health(health: Option<&str>) -> String {
+    match health {
+        Some("starting") => "Up (health: starting)".to_string(),
+        Some(health @ ("healthy" | "unhealthy")) => format!("Up ({})", health),
+        _ => "Up".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker_client::{HealthStatus, NetworkFilter};
+    use crate::test_util::TempDir;
+    use std::net::Ipv4Addr;
+
+    fn data_file(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data").join(name)
+    }
+
+    async fn list(name: &str, content: &str, config: DockerClientConfig) -> Result<Vec<NetworkInfo>> {
+        let dir = TempDir::new(name);
+        let path = dir.write("containers.json", content);
+        ContainerFile::new(&path, config).list_containers_network_info().await
+    }
+
+    #[tokio::test]
+    async fn reads_docker_inspect_output() {
+        let provider = ContainerFile::new(data_file("docker-inspect.json"), DockerClientConfig::default());
+
+        let infos = provider.list_containers_network_info().await.unwrap();
+
+        // The stopped container is skipped
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].names, vec!["project-web-1", "web"]);
+        assert_eq!(infos[0].ipv4_addresses, vec![Ipv4Addr::new(172, 18, 0, 2)]);
+        assert_eq!(infos[0].networks.len(), 1);
+        assert_eq!(infos[0].networks[0].subnets, vec!["172.18.0.0/16".parse::<IpNet>().unwrap()]);
+        assert_eq!(infos[0].health, HealthStatus::Healthy);
+    }
+
+    #[tokio::test]
+    async fn reads_own_format_with_naming_rules() {
+        let content = r#"{
+            "containers": [{
+                "name": "zen_montalcini",
+                "labels": { "docker-dns.aliases": "db, postgres" },
+                "health": "starting",
+                "networks": {
+                    "backend": { "ipv4": "10.1.0.5/24" },
+                    "frontend": { "ipv4": "10.2.0.5", "ipv6": "fd00::5/64" }
+                }
+            }],
+            "internal_networks": ["backend"]
+        }"#;
+        let config = DockerClientConfig {
+            normalize_names: true,
+            ..Default::default()
+        };
+
+        let infos = list("containers-own", content, config).await.unwrap();
+
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].names, vec!["zen-montalcini", "zen_montalcini", "db", "postgres"]);
+        assert_eq!(infos[0].ipv4_addresses, vec![Ipv4Addr::new(10, 2, 0, 5)]);
+        assert_eq!(infos[0].ipv6_addresses, vec!["fd00::5".parse::<std::net::Ipv6Addr>().unwrap()]);
+        assert_eq!(infos[0].health, HealthStatus::Starting);
+
+        let config = DockerClientConfig {
+            network_filter: NetworkFilter {
+                include_internal: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let infos = list("containers-own-internal", content, config).await.unwrap();
+        assert_eq!(infos[0].ipv4_addresses, vec![Ipv4Addr::new(10, 1, 0, 5), Ipv4Addr::new(10, 2, 0, 5)]);
+    }
+
+    #[tokio::test]
+    async fn ranks_addresses_by_preferred_network() {
+        let content = r#"{"containers": [{"name": "web", "networks": {
+            "backend": { "ipv4": "10.1.0.5/24" },
+            "frontend": { "ipv4": "10.2.0.5/24" },
+            "monitoring": { "ipv4": "10.3.0.5/24" }
+        }}]}"#;
+        let config = DockerClientConfig {
+            network_priority: vec!["frontend".parse().unwrap()],
+            ..Default::default()
+        };
+
+        let infos = list("containers-prefer-network", content, config).await.unwrap();
+
+        assert_eq!(
+            infos[0].ipv4_addresses,
+            vec![Ipv4Addr::new(10, 2, 0, 5), Ipv4Addr::new(10, 1, 0, 5), Ipv4Addr::new(10, 3, 0, 5)]
+        );
+        // Network name order is not a preference
+        assert_eq!(
+            infos[0].network_ranks,
+            HashMap::from([(IpAddr::from([10, 1, 0, 5]), 1), (IpAddr::from([10, 3, 0, 5]), 1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_unusable_files() {
+        let docker_ps = r#"{"ID":"0d1f","Names":"web","Networks":"bridge","Status":"Up 2 hours"}"#;
+        let error = list("containers-docker-ps", docker_ps, DockerClientConfig::default()).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("`docker ps` output has no addresses"));
+
+        let bad_address = r#"{"containers": [{"name": "web", "networks": {"bridge": {"ipv4": "10.0.0.300"}}}]}"#;
+        let error = list("containers-bad-address", bad_address, DockerClientConfig::default()).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("container 'web', network 'bridge': invalid address '10.0.0.300'"));
+    }
+}
@@ -1,11 +1,22 @@
-use crate::resolver::DnsResolver;
+use crate::access_control::AccessControl;
+use crate::answer_order::{AnswerOrder, AnswerOrderer};
+use crate::log_throttle::LogThrottle;
+use crate::metrics::Metrics;
+use crate::rate_limit::{RateLimitAction, RateLimitConfig, RateLimiter, ResponseKind};
+use crate::resolver::{DnsResolver, DnsResponse};
+use crate::zone_file::ZoneFile;
 use async_trait::async_trait;
 use hickory_server::authority::MessageResponseBuilder;
 use hickory_server::proto::op::{Header, MessageType, ResponseCode};
-use hickory_server::proto::rr::{RData, Record, RecordType};
-use hickory_server::server::{Request, RequestHandler, ResponseHandler, ResponseInfo};
-use log::{error, warn};
-use std::sync::Arc;
+use hickory_server::proto::rr::rdata::CNAME;
+use hickory_server::proto::rr::{Name, RData, Record, RecordType};
+use hickory_server::proto::serialize::binary::BinEncodable;
+use hickory_server::server::{Protocol, Request, RequestHandler, ResponseHandler, ResponseInfo};
+use log::{debug, error, warn};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 // Standard DNS UDP packet size limit (without EDNS)
 const DNS_UDP_MAX_SIZE: usize = 512;
@@ -15,30 +26,112 @@ const DNS_UDP_MAX_SIZE: usize = 512;
 // We use a conservative estimate
 const DNS_OVERHEAD_ESTIMATE: usize = 50;
 
+// Denied queries are logged at most once per interval
+const DENIED_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// TTL cap for stale answers, as RFC 8767 recommends, so clients soon ask again
+const STALE_TTL: u32 = 30;
+
+// Zone CNAMEs followed at most this many times for one answer, which also ends loops
+const MAX_CNAME_CHAIN: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct CustomHandlerConfig {
+    /// Only names ending with this suffix are answered. It is stripped before lookup.
+    pub suffix: String,
+    pub ttl: u32,
+    /// Answer aliases with a CNAME to the container's canonical name
+    pub cname_aliases: bool,
+    /// Order of the addresses when a name has several
+    pub answer_order: AnswerOrder,
+    /// Which clients may query which names
+    pub access_control: AccessControl,
+    /// Response rate limiting for UDP clients
+    pub rate_limit: RateLimitConfig,
+    /// Hand-written records. They take precedence over containers for their name and type;
+    /// a CNAME takes over its whole name.
+    pub zone_file: Option<Arc<ZoneFile>>,
+}
+
+impl Default for CustomHandlerConfig {
+    fn default() -> Self {
+        Self {
+            suffix: String::new(),
+            ttl: 60,
+            cname_aliases: false,
+            answer_order: AnswerOrder::Fixed,
+            access_control: AccessControl::default(),
+            rate_limit: RateLimitConfig::default(),
+            zone_file: None,
+        }
+    }
+}
+
 pub struct CustomHandler {
-    resolver: Arc<dyn DnsResolver>,
-    suffix: String,
-    ttl: u32,
+    /// Both can be swapped while serving
+    resolver: RwLock<Arc<dyn DnsResolver>>,
+    suffix: RwLock<String>,
+    config: CustomHandlerConfig,
+    orderer: AnswerOrderer,
+    metrics: Arc<Metrics>,
+    denied_log: LogThrottle,
+    rate_limiter: RateLimiter,
 }
 
 impl CustomHandler {
-    pub fn new(resolver: Arc<dyn DnsResolver>, suffix: String, ttl: u32) -> Self {
-        Self { resolver, suffix, ttl }
+    pub fn new(resolver: Arc<dyn DnsResolver>, config: CustomHandlerConfig) -> Self {
+        let orderer = AnswerOrderer::new(config.answer_order);
+        let rate_limiter = RateLimiter::new(config.rate_limit.clone());
+        Self {
+            resolver: RwLock::new(resolver),
+            suffix: RwLock::new(config.suffix.to_ascii_lowercase()),
+            config,
+            orderer,
+            metrics: Arc::new(Metrics::default()),
+            denied_log: LogThrottle::new(DENIED_LOG_INTERVAL),
+            rate_limiter,
+        }
+    }
+
+    /// Counts into these metrics, e.g. to share them with the resolver
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    pub fn resolver(&self) -> Arc<dyn DnsResolver> {
+        Arc::clone(&self.resolver.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Answers later queries from another resolver. Queries in progress finish with the old one.
+    pub fn set_resolver(&self, resolver: Arc<dyn DnsResolver>) {
+        *self.resolver.write().unwrap_or_else(|e| e.into_inner()) = resolver;
+    }
+
+    /// Answers later queries for another suffix (empty for all names)
+    pub fn set_suffix(&self, suffix: &str) {
+        *self.suffix.write().unwrap_or_else(|e| e.into_inner()) = suffix.to_ascii_lowercase();
     }
 
+    /// DNS names are case-insensitive, so lookups are done on a lowercase key
     fn normalize_domain(name: &str) -> String {
-        name.trim_end_matches('.').to_string()
+        name.trim_end_matches('.').to_ascii_lowercase()
     }
 
     /// Checks if the domain matches the configured suffix and strips it
     /// Returns Some(stripped_name) if it matches, None if it doesn't
     fn strip_suffix(&self, domain: &str) -> Option<String> {
-        if self.suffix.is_empty() {
+        let suffix = self.suffix.read().unwrap_or_else(|e| e.into_inner());
+        if suffix.is_empty() {
             return Some(domain.to_string()); // No suffix filter, accept all
         }
 
-        if domain.ends_with(&self.suffix) {
-            let stripped = &domain[..domain.len() - self.suffix.len()];
+        if domain.ends_with(suffix.as_str()) {
+            let stripped = &domain[..domain.len() - suffix.len()];
             Some(stripped.to_string())
         } else {
             None // Domain doesn't match suffix, reject
@@ -51,65 +144,34 @@ impl CustomHandler {
         mut response_handle: R,
     ) -> ResponseInfo {
         let request_info = request.request_info();
-        let query_name = request_info.query.name();
+        // Answer with the name exactly as asked (preserves DNS 0x20 case randomisation)
+        let query_name = request_info.query.original().name();
         let query_type = request_info.query.query_type();
         let domain = Self::normalize_domain(&query_name.to_string());
+        let client = request.src().ip().to_canonical();
 
         let builder = MessageResponseBuilder::from_message_request(request);
         let mut header = Header::response_from_request(request_info.header);
 
-        let mut result: Vec<Record> = vec![];
+        Metrics::increment(&self.metrics.queries);
 
-        // Check if domain matches suffix filter and strip it
-        match self.strip_suffix(&domain) {
-            Some(container_name) => {
-                // Domain matches suffix (or no suffix configured), look it up
-                if let Some(dns_response) = self.resolver.resolve(&container_name).await {
-                    header.set_response_code(ResponseCode::NoError);
-                    header.set_authoritative(true);
-
-                    // Build records based on query type
-                    let mut records = Vec::new();
-
-                    match query_type {
-                        RecordType::A => {
-                            // Only return A records for A queries
-                            for ipv4 in &dns_response.ipv4_addresses {
-                                let record = Record::from_rdata(
-                                    query_name.clone().into(),
-                                    self.ttl,
-                                    RData::A((*ipv4).into())
-                                );
-                                records.push(record);
-                            }
-                        }
-                        RecordType::AAAA => {
-                            // Only return AAAA records for AAAA queries
-                            for ipv6 in &dns_response.ipv6_addresses {
-                                let record = Record::from_rdata(
-                                    query_name.clone().into(),
-                                    self.ttl,
-                                    RData::AAAA((*ipv6).into())
-                                );
-                                records.push(record);
-                            }
-                        }
-                        _ => {
-                            // For other query types, return empty response with NoError
-                            // This is standard DNS behavior for unsupported query types
-                        }
-                    }
-
-                    // Apply size limit to prevent exceeding UDP packet size
-                    result = Self::apply_size_limit(records, &domain, query_name.len());
-                } else {
-                    // Container not found
-                    header.set_response_code(ResponseCode::NXDomain);
-                }
+        let mut result = if self.config.access_control.is_allowed(client, &domain) {
+            self.answer(query_name, query_type, &domain, client, &mut header).await
+        } else {
+            self.report_denied(client, &domain);
+            if self.config.access_control.drop_denied {
+                return ResponseInfo::from(*request_info.header);
             }
-            None => {
-                // Domain doesn't match suffix filter, refuse to answer
-                header.set_response_code(ResponseCode::Refused);
+            header.set_response_code(ResponseCode::Refused);
+            vec![]
+        };
+
+        // Only UDP sources can be spoofed to reflect responses at a victim
+        if matches!(request.protocol(), Protocol::Udp) {
+            match self.rate_limit(client, &mut header) {
+                RateLimitAction::Send => {}
+                RateLimitAction::Slip => result.clear(),
+                RateLimitAction::Drop => return ResponseInfo::from(*request_info.header),
             }
         }
 
@@ -129,6 +191,220 @@ impl CustomHandler {
         }
     }
 
+    /// Looks up the query and sets the response code. Returns the answer records.
+    async fn answer(
+        &self,
+        query_name: &Name,
+        query_type: RecordType,
+        domain: &str,
+        client: IpAddr,
+        header: &mut Header,
+    ) -> Vec<Record> {
+        // Check if domain matches suffix filter and strip it
+        let Some(container_name) = self.strip_suffix(domain) else {
+            // Domain doesn't match suffix filter, refuse to answer
+            header.set_response_code(ResponseCode::Refused);
+            return vec![];
+        };
+
+        // Zone records of the asked type (or a CNAME) win over containers
+        let zone_records = self.config.zone_file.as_ref().and_then(|zone| zone.lookup(domain));
+        if let Some(zone_records) = &zone_records {
+            let records = Self::zone_answer(zone_records, query_name, query_type);
+            if !records.is_empty() {
+                let Some(records) = self.follow_zone_cname(records, domain, query_type, client).await else {
+                    debug!("CNAME loop or chain over {} CNAMEs in the zone file at '{}'", MAX_CNAME_CHAIN, domain);
+                    header.set_response_code(ResponseCode::ServFail);
+                    return vec![];
+                };
+                header.set_response_code(ResponseCode::NoError);
+                header.set_authoritative(true);
+                return Self::apply_size_limit(records, domain, query_name.len());
+            }
+        }
+
+        // Domain matches suffix (or no suffix configured), look it up
+        let Some(dns_response) = self.resolver().resolve_for_client(&container_name, client).await else {
+            // The name exists if the zone has other record types for it. Without
+            // containers yet, we can't tell whether the name exists.
+            let response_code = if zone_records.is_some() {
+                ResponseCode::NoError
+            } else if !self.resolver().is_ready() {
+                ResponseCode::ServFail
+            } else {
+                ResponseCode::NXDomain
+            };
+            header.set_response_code(response_code);
+            header.set_authoritative(zone_records.is_some());
+            return vec![];
+        };
+
+        header.set_response_code(ResponseCode::NoError);
+        header.set_authoritative(true);
+
+        let records = self.build_records(query_name, query_type, &container_name, &dns_response);
+
+        // Apply size limit to prevent exceeding UDP packet size
+        Self::apply_size_limit(records, domain, query_name.len())
+    }
+
+    fn report_denied(&self, client: IpAddr, domain: &str) {
+        Metrics::increment(&self.metrics.queries_denied);
+
+        if let Some(suppressed) = self.denied_log.check() {
+            warn!(
+                "Denied query for '{}' from {} ({} more denied queries not logged)",
+                domain, client, suppressed
+            );
+        }
+    }
+
+    /// Applies response rate limiting. A slipped response is marked as truncated,
+    /// so a real client retries over TCP, and its records must be left out.
+    fn rate_limit(&self, client: IpAddr, header: &mut Header) -> RateLimitAction {
+        let kind = match header.response_code() {
+            ResponseCode::NXDomain => ResponseKind::NxDomain,
+            _ => ResponseKind::Answer,
+        };
+
+        let action = self.rate_limiter.check(client, kind);
+        match action {
+            RateLimitAction::Send => {}
+            RateLimitAction::Slip => {
+                Metrics::increment(&self.metrics.responses_slipped);
+                header.set_truncated(true);
+            }
+            RateLimitAction::Drop => Metrics::increment(&self.metrics.responses_dropped),
+        }
+        action
+    }
+
+    /// Builds the answer records for a resolved name.
+    /// Aliases get a CNAME to the canonical name first, followed by the address chain.
+    fn build_records(
+        &self,
+        query_name: &Name,
+        query_type: RecordType,
+        container_name: &str,
+        dns_response: &DnsResponse,
+    ) -> Vec<Record> {
+        let mut records = Vec::new();
+        let mut owner = query_name.clone();
+        let ttl = if dns_response.stale { self.config.ttl.min(STALE_TTL) } else { self.config.ttl };
+
+        let cname_target = self.cname_target(container_name, dns_response);
+        let is_alias = cname_target.is_some();
+        if let Some(target) = cname_target {
+            records.push(Record::from_rdata(owner, ttl, RData::CNAME(CNAME(target.clone()))));
+            owner = target;
+        }
+
+        let (with_ipv4, with_ipv6) = match query_type {
+            // Only return A records for A queries
+            RecordType::A => (true, false),
+            // Only return AAAA records for AAAA queries
+            RecordType::AAAA => (false, true),
+            // Complete the chain when a CNAME is asked for directly
+            RecordType::CNAME => (is_alias, is_alias),
+            // For other query types, return empty response with NoError (or just the CNAME)
+            // This is standard DNS behavior for unsupported query types
+            _ => (false, false),
+        };
+
+        if with_ipv4 {
+            for ipv4 in self.orderer.order(container_name, &dns_response.ipv4_addresses, dns_response) {
+                records.push(Record::from_rdata(owner.clone(), ttl, RData::A(ipv4.into())));
+            }
+        }
+
+        if with_ipv6 {
+            for ipv6 in self.orderer.order(container_name, &dns_response.ipv6_addresses, dns_response) {
+                records.push(Record::from_rdata(owner.clone(), ttl, RData::AAAA(ipv6.into())));
+            }
+        }
+
+        records
+    }
+
+    /// Picks the zone records answering the query, owned by the name as asked
+    fn zone_answer(zone_records: &[Record], query_name: &Name, query_type: RecordType) -> Vec<Record> {
+        let cname = zone_records.iter().any(|record| record.record_type() == RecordType::CNAME);
+        zone_records
+            .iter()
+            .filter(|record| {
+                let record_type = record.record_type();
+                record_type == query_type || (cname && record_type == RecordType::CNAME)
+            })
+            .map(|record| {
+                let mut record = record.clone();
+                record.set_name(query_name.clone());
+                record
+            })
+            .collect()
+    }
+
+    /// Appends the addresses of a zone CNAME's target when the target is one of our names,
+    /// from the zone or else the containers, following further zone CNAMEs.
+    /// Returns None on a CNAME loop or a chain longer than `MAX_CNAME_CHAIN`.
+    async fn follow_zone_cname(
+        &self,
+        mut records: Vec<Record>,
+        domain: &str,
+        query_type: RecordType,
+        client: IpAddr,
+    ) -> Option<Vec<Record>> {
+        if !matches!(query_type, RecordType::A | RecordType::AAAA) {
+            return Some(records);
+        }
+
+        let mut visited = HashSet::from([domain.to_string()]);
+        while let Some(target) = records.last().and_then(Record::data).and_then(RData::as_cname).map(|cname| cname.0.clone()) {
+            let domain = Self::normalize_domain(&target.to_string());
+            let Some(container_name) = self.strip_suffix(&domain) else {
+                break;
+            };
+            if !visited.insert(domain.clone()) || visited.len() > MAX_CNAME_CHAIN + 1 {
+                return None;
+            }
+
+            let zone_records = self.config.zone_file.as_ref().and_then(|zone| zone.lookup(&domain));
+            let chained = zone_records.map(|zone_records| Self::zone_answer(&zone_records, &target, query_type));
+            match chained {
+                Some(chained) if !chained.is_empty() => records.extend(chained),
+                _ => {
+                    if let Some(dns_response) = self.resolver().resolve_for_client(&container_name, client).await {
+                        records.extend(self.build_records(&target, query_type, &container_name, &dns_response));
+                    }
+                    break;
+                }
+            }
+        }
+        Some(records)
+    }
+
+    /// Returns the fully qualified canonical name if CNAME answers are enabled
+    /// and the looked up name is an alias
+    fn cname_target(&self, container_name: &str, dns_response: &DnsResponse) -> Option<Name> {
+        if !self.config.cname_aliases {
+            return None;
+        }
+
+        let canonical_name = dns_response.canonical_name.as_deref()?;
+        if canonical_name == container_name {
+            return None;
+        }
+
+        let suffix = self.suffix.read().unwrap_or_else(|e| e.into_inner());
+        let target = format!("{}{}.", canonical_name, suffix);
+        match Name::from_ascii(&target) {
+            Ok(name) => Some(name),
+            Err(e) => {
+                warn!("Cannot use '{}' as CNAME target: {:#}", target, e);
+                None
+            }
+        }
+    }
+
     /// Applies DNS UDP packet size limit, preferring to keep records that fit
     /// Returns as many records as will fit within the size limit
     fn apply_size_limit(records: Vec<Record>, domain: &str, query_name_len: usize) -> Vec<Record> {
@@ -146,11 +422,14 @@ impl CustomHandler {
             // Estimate record size:
             // Name (compressed, usually 2 bytes pointer)
             // Type (2 bytes) + Class (2 bytes) + TTL (4 bytes) + RDLength (2 bytes)
-            // RData: 4 bytes for A, 16 bytes for AAAA
+            // RData: 4 bytes for A, 16 bytes for AAAA, the target name for CNAME,
+            // the encoded size for other (zone file) records
             let record_size = match record.data() {
                 Some(RData::A(_)) => 2 + 2 + 2 + 4 + 2 + 4,  // ~16 bytes
                 Some(RData::AAAA(_)) => 2 + 2 + 2 + 4 + 2 + 16,  // ~28 bytes
-                _ => 0, // We don't have any other records
+                Some(RData::CNAME(target)) => 2 + 2 + 2 + 4 + 2 + target.len(),
+                Some(rdata) => 2 + 2 + 2 + 4 + 2 + rdata.to_bytes().map_or(0, |bytes| bytes.len()),
+                None => 0,
             };
 
             if current_size + record_size > available_space {
@@ -194,6 +473,39 @@ impl RequestHandler for CustomHandler {
 mod tests {
     use super::*;
     use crate::resolver::StaticResolver;
+    use crate::test_util::TempDir;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::str::FromStr;
+
+    fn docker_suffix_config() -> CustomHandlerConfig {
+        CustomHandlerConfig {
+            suffix: ".docker".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn cname_handler() -> CustomHandler {
+        let resolver = Arc::new(StaticResolver::new());
+        CustomHandler::new(resolver, CustomHandlerConfig {
+            cname_aliases: true,
+            ..docker_suffix_config()
+        })
+    }
+
+    fn web_response() -> DnsResponse {
+        DnsResponse::new(
+            vec![Ipv4Addr::new(172, 17, 0, 2)],
+            vec![Ipv6Addr::from_str("2001:db8::2").unwrap()],
+        )
+        .with_canonical_name("project-web-1")
+    }
+
+    fn record_summary(records: &[Record]) -> Vec<(String, RecordType)> {
+        records
+            .iter()
+            .map(|record| (record.name().to_string(), record.record_type()))
+            .collect()
+    }
 
     #[test]
     fn normalizes_domain_by_removing_trailing_dot() {
@@ -202,20 +514,48 @@ mod tests {
         assert_eq!(CustomHandler::normalize_domain("my.example.local."), "my.example.local");
     }
 
+    #[test]
+    fn normalizes_domain_to_lowercase() {
+        assert_eq!(CustomHandler::normalize_domain("MyApp.DOCKER."), "myapp.docker");
+        assert_eq!(CustomHandler::normalize_domain("mYaPp.dOcKeR"), "myapp.docker");
+    }
+
     #[test]
     fn strips_suffix_when_configured() {
         let resolver = Arc::new(StaticResolver::new());
-        let handler = CustomHandler::new(resolver, ".docker".to_string(), 60);
+        let handler = CustomHandler::new(resolver, docker_suffix_config());
 
         assert_eq!(handler.strip_suffix("myapp.docker"), Some("myapp".to_string()));
         assert_eq!(handler.strip_suffix("nginx.docker"), Some("nginx".to_string()));
         assert_eq!(handler.strip_suffix("example.com"), None);
     }
 
+    #[test]
+    fn strips_suffix_configured_in_mixed_case() {
+        let resolver = Arc::new(StaticResolver::new());
+        let handler = CustomHandler::new(resolver, CustomHandlerConfig {
+            suffix: ".Docker".to_string(),
+            ..Default::default()
+        });
+
+        assert_eq!(handler.strip_suffix("myapp.docker"), Some("myapp".to_string()));
+    }
+
+    #[test]
+    fn changes_suffix_while_serving() {
+        let resolver = Arc::new(StaticResolver::new());
+        let handler = CustomHandler::new(resolver, docker_suffix_config());
+
+        handler.set_suffix(".Local");
+
+        assert_eq!(handler.strip_suffix("myapp.local"), Some("myapp".to_string()));
+        assert_eq!(handler.strip_suffix("myapp.docker"), None);
+    }
+
     #[test]
     fn accepts_all_domains_when_no_suffix_configured() {
         let resolver = Arc::new(StaticResolver::new());
-        let handler = CustomHandler::new(resolver, "".to_string(), 60);
+        let handler = CustomHandler::new(resolver, CustomHandlerConfig::default());
 
         assert_eq!(handler.strip_suffix("myapp.docker"), Some("myapp.docker".to_string()));
         assert_eq!(handler.strip_suffix("example.com"), Some("example.com".to_string()));
@@ -225,11 +565,312 @@ mod tests {
     #[test]
     fn handles_nested_domain_with_suffix() {
         let resolver = Arc::new(StaticResolver::new());
-        let handler = CustomHandler::new(resolver, ".docker".to_string(), 60);
+        let handler = CustomHandler::new(resolver, docker_suffix_config());
 
         assert_eq!(
             handler.strip_suffix("app.production.docker"),
             Some("app.production".to_string())
         );
     }
+
+    #[test]
+    fn answers_alias_with_cname_and_address_chain() {
+        let handler = cname_handler();
+        let query_name = Name::from_ascii("Web.Docker.").unwrap();
+
+        let records = handler.build_records(&query_name, RecordType::A, "web", &web_response());
+
+        assert_eq!(
+            record_summary(&records),
+            vec![
+                ("Web.Docker.".to_string(), RecordType::CNAME),
+                ("project-web-1.docker.".to_string(), RecordType::A),
+            ]
+        );
+        let target = records[0].data().unwrap().as_cname().unwrap();
+        assert_eq!(target.0.to_string(), "project-web-1.docker.");
+    }
+
+    #[test]
+    fn answers_direct_cname_query_with_full_chain() {
+        let handler = cname_handler();
+        let query_name = Name::from_ascii("web.docker.").unwrap();
+
+        let records = handler.build_records(&query_name, RecordType::CNAME, "web", &web_response());
+
+        assert_eq!(
+            record_summary(&records),
+            vec![
+                ("web.docker.".to_string(), RecordType::CNAME),
+                ("project-web-1.docker.".to_string(), RecordType::A),
+                ("project-web-1.docker.".to_string(), RecordType::AAAA),
+            ]
+        );
+    }
+
+    #[test]
+    fn answers_canonical_name_without_cname() {
+        let handler = cname_handler();
+        let query_name = Name::from_ascii("project-web-1.docker.").unwrap();
+
+        let records = handler.build_records(&query_name, RecordType::A, "project-web-1", &web_response());
+        assert_eq!(record_summary(&records), vec![("project-web-1.docker.".to_string(), RecordType::A)]);
+
+        let records = handler.build_records(&query_name, RecordType::CNAME, "project-web-1", &web_response());
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn answers_stale_names_with_short_ttl() {
+        let handler = cname_handler();
+        let query_name = Name::from_ascii("web.docker.").unwrap();
+        let mut response = web_response();
+
+        let records = handler.build_records(&query_name, RecordType::A, "web", &response);
+        assert!(records.iter().all(|record| record.ttl() == 60));
+
+        response.stale = true;
+        let records = handler.build_records(&query_name, RecordType::A, "web", &response);
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.ttl() == STALE_TTL));
+    }
+
+    #[test]
+    fn answers_alias_with_addresses_when_cname_disabled() {
+        let resolver = Arc::new(StaticResolver::new());
+        let handler = CustomHandler::new(resolver, docker_suffix_config());
+        let query_name = Name::from_ascii("web.docker.").unwrap();
+
+        let records = handler.build_records(&query_name, RecordType::A, "web", &web_response());
+
+        assert_eq!(record_summary(&records), vec![("web.docker.".to_string(), RecordType::A)]);
+    }
+
+    // 40 A records don't fit in a UDP response, so only the first ones survive truncation
+    fn many_addresses_response() -> DnsResponse {
+        let addresses = (1..=40).map(|i| Ipv4Addr::new(172, 17, 0, i)).collect();
+        DnsResponse::new(addresses, vec![])
+    }
+
+    fn truncated_answer(handler: &CustomHandler, dns_response: &DnsResponse) -> Vec<Ipv4Addr> {
+        let query_name = Name::from_ascii("web.docker.").unwrap();
+        let records = handler.build_records(&query_name, RecordType::A, "web", dns_response);
+        CustomHandler::apply_size_limit(records, "web.docker", query_name.len())
+            .iter()
+            .map(|record| record.data().unwrap().as_a().unwrap().0)
+            .collect()
+    }
+
+    fn ordered_handler(answer_order: AnswerOrder) -> CustomHandler {
+        let resolver = Arc::new(StaticResolver::new());
+        CustomHandler::new(resolver, CustomHandlerConfig {
+            answer_order,
+            ..docker_suffix_config()
+        })
+    }
+
+    #[test]
+    fn fixed_order_always_truncates_to_same_addresses() {
+        let handler = ordered_handler(AnswerOrder::Fixed);
+        let dns_response = many_addresses_response();
+
+        let first = truncated_answer(&handler, &dns_response);
+        assert!(first.len() < 40);
+        assert_eq!(first, dns_response.ipv4_addresses[..first.len()]);
+        assert_eq!(truncated_answer(&handler, &dns_response), first);
+    }
+
+    #[test]
+    fn round_robin_order_reaches_truncated_addresses() {
+        let handler = ordered_handler(AnswerOrder::RoundRobin);
+        let dns_response = many_addresses_response();
+
+        let answers = (0..40).map(|_| truncated_answer(&handler, &dns_response)).collect::<Vec<_>>();
+
+        for (i, answer) in answers.iter().enumerate() {
+            assert!(answer.len() < 40);
+            assert_eq!(answer[0], dns_response.ipv4_addresses[i]);
+        }
+    }
+
+    #[test]
+    fn random_order_reaches_truncated_addresses() {
+        let handler = ordered_handler(AnswerOrder::Random);
+        let dns_response = many_addresses_response();
+
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..100 {
+            let answer = truncated_answer(&handler, &dns_response);
+            assert!(answer.len() < 40);
+            assert_eq!(answer.iter().collect::<std::collections::HashSet<_>>().len(), answer.len());
+            seen.extend(answer);
+        }
+
+        assert_eq!(seen.len(), 40);
+    }
+
+    #[test]
+    fn weighted_order_favours_heavy_addresses_after_truncation() {
+        let handler = ordered_handler(AnswerOrder::Weighted);
+        let mut dns_response = many_addresses_response();
+        let canary = Ipv4Addr::new(172, 17, 0, 1);
+        dns_response.weights = dns_response
+            .ipv4_addresses
+            .iter()
+            .map(|ip| (IpAddr::V4(*ip), if *ip == canary { 1 } else { 1000 }))
+            .collect();
+
+        let mut canary_first = 0;
+        let mut canary_truncated = 0;
+        for _ in 0..1000 {
+            let answer = truncated_answer(&handler, &dns_response);
+            assert!(answer.len() < 40);
+            if answer[0] == canary {
+                canary_first += 1;
+            }
+            if !answer.contains(&canary) {
+                canary_truncated += 1;
+            }
+        }
+
+        // The canary should almost never come first and mostly be truncated away
+        assert!(canary_first < 20, "canary came first {} times", canary_first);
+        assert!(canary_truncated > 500, "canary was truncated {} times", canary_truncated);
+    }
+
+    fn zone_handler(name: &str) -> CustomHandler {
+        // The zone is read on load, so the directory can go away with this function
+        let dir = TempDir::new(name);
+        let path = dir.write(
+            "docker.zone",
+            concat!(
+                "_ldap._tcp 60 IN SRV 10 5 389 ldap\ndb 60 IN A 192.168.1.10\nwww 60 IN CNAME web\n",
+                "legacy 60 IN CNAME www\ndatabase 60 IN CNAME db\nexternal 60 IN CNAME example.com.\n",
+                "ping 60 IN CNAME pong\npong 60 IN CNAME ping\n",
+                "hop1 60 IN CNAME hop2\nhop2 60 IN CNAME hop3\nhop3 60 IN CNAME hop4\nhop4 60 IN CNAME hop5\nhop5 60 IN CNAME hop6\nhop6 60 IN CNAME hop7\nhop7 60 IN CNAME hop8\nhop8 60 IN CNAME hop9\nhop9 60 IN CNAME web\n",
+            ),
+        );
+
+        let mut resolver = StaticResolver::new();
+        resolver.add_mapping("db", Ipv4Addr::new(172, 17, 0, 2));
+        resolver.add_mapping("db", Ipv6Addr::from_str("fd00::2").unwrap());
+        resolver.add_mapping("www", Ipv4Addr::new(172, 17, 0, 3));
+        resolver.add_mapping("web", Ipv4Addr::new(172, 17, 0, 4));
+        CustomHandler::new(Arc::new(resolver), CustomHandlerConfig {
+            zone_file: Some(Arc::new(ZoneFile::load(path, ".docker").unwrap())),
+            ..docker_suffix_config()
+        })
+    }
+
+    async fn zone_answer(handler: &CustomHandler, name: &str, query_type: RecordType) -> (ResponseCode, Vec<Record>) {
+        let query_name = Name::from_ascii(name).unwrap();
+        let domain = CustomHandler::normalize_domain(name);
+        let mut header = Header::new();
+        let records = handler
+            .answer(&query_name, query_type, &domain, IpAddr::from([127, 0, 0, 1]), &mut header)
+            .await;
+        (header.response_code(), records)
+    }
+
+    #[tokio::test]
+    async fn answers_zone_records_of_other_types() {
+        let handler = zone_handler("handler-zone-types");
+
+        let (code, records) = zone_answer(&handler, "_LDAP._tcp.docker.", RecordType::SRV).await;
+
+        assert_eq!(code, ResponseCode::NoError);
+        assert_eq!(record_summary(&records), vec![("_LDAP._tcp.docker.".to_string(), RecordType::SRV)]);
+    }
+
+    #[tokio::test]
+    async fn zone_records_take_precedence_per_type() {
+        let handler = zone_handler("handler-zone-precedence");
+
+        let (_, records) = zone_answer(&handler, "db.docker.", RecordType::A).await;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].data().unwrap().as_a().unwrap().0, Ipv4Addr::new(192, 168, 1, 10));
+
+        // The zone has no AAAA record for the name, so the container's is used
+        let (_, records) = zone_answer(&handler, "db.docker.", RecordType::AAAA).await;
+        assert_eq!(record_summary(&records), vec![("db.docker.".to_string(), RecordType::AAAA)]);
+
+        // A CNAME takes over the whole name: the answer is its target's address, not the container's
+        let (_, records) = zone_answer(&handler, "www.docker.", RecordType::A).await;
+        assert_eq!(records[0].record_type(), RecordType::CNAME);
+        assert_eq!(records[1].data().unwrap().as_a().unwrap().0, Ipv4Addr::new(172, 17, 0, 4));
+    }
+
+    #[tokio::test]
+    async fn follows_zone_cnames_to_our_names() {
+        let handler = zone_handler("handler-zone-cname");
+
+        // To a container, through a chain of zone CNAMEs
+        let (_, records) = zone_answer(&handler, "legacy.docker.", RecordType::A).await;
+        assert_eq!(
+            record_summary(&records),
+            vec![
+                ("legacy.docker.".to_string(), RecordType::CNAME),
+                ("www.docker.".to_string(), RecordType::CNAME),
+                ("web.docker.".to_string(), RecordType::A),
+            ]
+        );
+        assert_eq!(records[2].data().unwrap().as_a().unwrap().0, Ipv4Addr::new(172, 17, 0, 4));
+
+        // To a zone name, whose zone address wins
+        let (_, records) = zone_answer(&handler, "database.docker.", RecordType::A).await;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].data().unwrap().as_a().unwrap().0, Ipv4Addr::new(192, 168, 1, 10));
+
+        // Other domains are left to the client, and loops fail
+        let (_, records) = zone_answer(&handler, "external.docker.", RecordType::A).await;
+        assert_eq!(record_summary(&records), vec![("external.docker.".to_string(), RecordType::CNAME)]);
+        let (code, records) = zone_answer(&handler, "ping.docker.", RecordType::A).await;
+        assert_eq!(code, ResponseCode::ServFail);
+        assert!(records.is_empty());
+
+        // Chains are followed up to the limit
+        let (code, records) = zone_answer(&handler, "hop2.docker.", RecordType::A).await;
+        assert_eq!(code, ResponseCode::NoError);
+        assert_eq!(records.len(), MAX_CNAME_CHAIN + 1);
+        let (code, _) = zone_answer(&handler, "hop1.docker.", RecordType::A).await;
+        assert_eq!(code, ResponseCode::ServFail);
+    }
+
+    #[tokio::test]
+    async fn answers_no_data_for_zone_names_without_the_type() {
+        let handler = zone_handler("handler-zone-no-data");
+
+        let (code, records) = zone_answer(&handler, "_ldap._tcp.docker.", RecordType::TXT).await;
+        assert_eq!(code, ResponseCode::NoError);
+        assert!(records.is_empty());
+
+        let (code, _) = zone_answer(&handler, "unknown.docker.", RecordType::TXT).await;
+        assert_eq!(code, ResponseCode::NXDomain);
+    }
+
+    /// Like a Docker resolver before its first sync
+    struct UnsyncedResolver;
+
+    #[async_trait::async_trait]
+    impl DnsResolver for UnsyncedResolver {
+        async fn resolve(&self, _domain: &str) -> Option<Arc<DnsResponse>> {
+            None
+        }
+
+        fn is_ready(&self) -> bool {
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_servfail_until_resolver_is_ready() {
+        let handler = CustomHandler::new(Arc::new(UnsyncedResolver), docker_suffix_config());
+
+        let (code, _) = zone_answer(&handler, "web.docker.", RecordType::A).await;
+        assert_eq!(code, ResponseCode::ServFail);
+
+        handler.set_resolver(Arc::new(StaticResolver::new()));
+        let (code, _) = zone_answer(&handler, "web.docker.", RecordType::A).await;
+        assert_eq!(code, ResponseCode::NXDomain);
+    }
 }
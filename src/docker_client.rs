@@ -1,14 +1,46 @@
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use bollard::Docker;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use bollard::secret::ContainerSummary;
+use ipnet::IpNet;
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::Mutex;
 
+use crate::hostname::to_hostname;
 use crate::strip_prefix_sane::SaneStrip;
 
+/// Container label that enables wildcard subdomains for that container
+pub const WILDCARD_LABEL: &str = "docker-dns.wildcard";
+
+/// Container label with extra names (aliases) for the container, separated by commas or whitespace
+pub const ALIASES_LABEL: &str = "docker-dns.aliases";
+
+/// Container label with the networks to publish addresses from, separated by commas or whitespace.
+/// Overrides the configured network filter for that container.
+pub const NETWORKS_LABEL: &str = "docker-dns.networks";
+
+/// Container label with the network (name, ID or subnet) whose addresses should be returned first
+pub const PREFER_NETWORK_LABEL: &str = "docker-dns.prefer-network";
+
+/// Container label with the weight of the container's addresses for weighted answer ordering
+pub const WEIGHT_LABEL: &str = "docker-dns.weight";
+
+/// Label set by Docker Compose with the service name of the container
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+
 #[derive(Debug, Clone)]
 pub struct DockerClientConfig {
     pub socket_path: String,
     pub timeout_seconds: u64,
+    /// Also register a hostname-safe variant of each container name
+    pub normalize_names: bool,
+    /// Selects which networks container addresses are published from
+    pub network_filter: NetworkFilter,
+    /// Addresses are ordered by the first matching network in this list, then by network name
+    pub network_priority: Vec<NetworkSelector>,
 }
 
 impl Default for DockerClientConfig {
@@ -16,15 +48,192 @@ impl Default for DockerClientConfig {
         Self {
             socket_path: "/var/run/docker.sock".to_string(),
             timeout_seconds: 10,
+            normalize_names: false,
+            network_filter: NetworkFilter::default(),
+            network_priority: vec![],
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// What to do if Docker doesn't answer at startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StartupPolicy {
+    /// Serve SERVFAIL for unknown names until the first successful sync, reconnecting in the background
+    #[default]
+    Wait,
+    /// Exit with a distinct exit code
+    Fail,
+}
+
+impl FromStr for StartupPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wait" => Ok(Self::Wait),
+            "fail" => Ok(Self::Fail),
+            _ => Err(format!("invalid Docker startup policy '{}' (expected wait or fail)", s)),
+        }
+    }
+}
+
+/// Shortest network ID prefix accepted, Docker's short ID length
+const MIN_NETWORK_ID_PREFIX_LEN: usize = 12;
+
+/// Selects a Docker network by name, ID (or ID prefix) or by a subnet containing the address
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkSelector {
+    NameOrId(String),
+    Subnet(IpNet),
+}
+
+impl FromStr for NetworkSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("network selector must not be empty".to_string());
+        }
+
+        if let Ok(subnet) = s.parse::<IpNet>() {
+            return Ok(Self::Subnet(subnet));
+        }
+        // Docker network names are [a-zA-Z0-9][a-zA-Z0-9_.-]*, IDs are hexadecimal
+        let valid_name = s.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && s.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+        if !valid_name {
+            return Err(format!("invalid network name, ID or subnet '{}'", s));
+        }
+        Ok(Self::NameOrId(s.to_string()))
+    }
+}
+
+impl NetworkSelector {
+    /// `network_names` are the names of all networks of the container: a value naming one of them
+    /// is not also taken as an ID prefix, and neither is a value shorter than a short ID
+    fn matches(&self, endpoint: &Endpoint, network_names: &HashSet<&str>) -> bool {
+        match self {
+            Self::NameOrId(value) => {
+                if endpoint.network_name == *value {
+                    return true;
+                }
+                value.len() >= MIN_NETWORK_ID_PREFIX_LEN
+                    && !network_names.contains(value.as_str())
+                    && !endpoint.network_id.is_empty()
+                    && endpoint.network_id.starts_with(value.as_str())
+            }
+            Self::Subnet(subnet) => endpoint.addresses().any(|ip| subnet.contains(&ip)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NetworkFilter {
+    /// If not empty, only addresses on these networks are published
+    pub allow: Vec<NetworkSelector>,
+    /// Addresses on these networks are never published
+    pub deny: Vec<NetworkSelector>,
+    /// Publish addresses on networks marked internal (unreachable from the host)
+    pub include_internal: bool,
+}
+
+impl NetworkFilter {
+    fn is_published(
+        &self,
+        endpoint: &Endpoint,
+        network_names: &HashSet<&str>,
+        internal_networks: &HashSet<String>,
+    ) -> bool {
+        if !self.include_internal && internal_networks.contains(&endpoint.network_id) {
+            return false;
+        }
+
+        let matches = |selector: &NetworkSelector| selector.matches(endpoint, network_names);
+        if !self.allow.is_empty() && !self.allow.iter().any(matches) {
+            return false;
+        }
+
+        !self.deny.iter().any(matches)
+    }
+}
+
+/// A container's attachment to a single network
+#[derive(Debug, Clone, Default)]
+struct Endpoint {
+    network_name: String,
+    network_id: String,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    subnets: Vec<IpNet>,
+}
+
+impl Endpoint {
+    fn addresses(&self) -> impl Iterator<Item = IpAddr> {
+        self.ipv4
+            .map(IpAddr::V4)
+            .into_iter()
+            .chain(self.ipv6.map(IpAddr::V6))
+    }
+}
+
+/// A container's addresses on one network, with that network's subnets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkAttachment {
+    /// IPv4 and IPv6 subnets of the network
+    pub subnets: Vec<IpNet>,
+    pub addresses: Vec<IpAddr>,
+}
+
+impl NetworkAttachment {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.subnets.iter().any(|subnet| subnet.contains(ip))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct NetworkInfo {
+    /// All names of the container. The first one is its canonical name,
+    /// the remaining ones are aliases.
     pub names: Vec<String>,
     pub ipv4_addresses: Vec<Ipv4Addr>,
     pub ipv6_addresses: Vec<Ipv6Addr>,
+    /// The networks the addresses were published from
+    pub networks: Vec<NetworkAttachment>,
+    /// Subdomains of the names resolve to this container as well
+    pub wildcard: bool,
+    pub health: HealthStatus,
+    /// Weight of the addresses for weighted answer ordering, if set
+    pub weight: Option<u32>,
+    /// Rank of the network of each address by network preference, 0 for the most preferred
+    /// network of the container. Addresses not listed have rank 0.
+    pub network_ranks: HashMap<IpAddr, u32>,
+}
+
+/// Health of a container as reported by its healthcheck
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HealthStatus {
+    /// The container has no healthcheck
+    #[default]
+    None,
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+impl HealthStatus {
+    /// Parses the health from a container status such as "Up 5 minutes (healthy)"
+    fn from_status(status: &str) -> Self {
+        if status.ends_with("(health: starting)") {
+            Self::Starting
+        } else if status.ends_with("(unhealthy)") {
+            Self::Unhealthy
+        } else if status.ends_with("(healthy)") {
+            Self::Healthy
+        } else {
+            Self::None
+        }
+    }
 }
 
 #[async_trait]
@@ -33,11 +242,16 @@ pub trait NetworkInfoProvider: Send + Sync {
 }
 
 pub struct DockerClient {
-    client: Docker,
+    /// Set once the socket exists; Docker may create it after we start
+    client: Mutex<Option<Docker>>,
+    config: DockerClientConfig,
+    label_warnings: LabelWarnings,
 }
 
 impl DockerClient {
-    /// Creates a new Docker client with the specified configuration
+    /// Creates a new Docker client with the specified configuration.
+    /// Nothing is connected yet; if the socket doesn't exist, it is looked for again on every use.
+    /// Use `check_connection` to find out whether Docker answers.
     ///
     /// # Arguments
     /// * `config` - Docker client configuration
@@ -45,73 +259,333 @@ impl DockerClient {
     /// # Returns
     /// Result containing the DockerClient or an error
     pub fn new(config: DockerClientConfig) -> Result<Self> {
-        let client = Docker::connect_with_socket(
-            &config.socket_path,
-            config.timeout_seconds,
-            bollard::API_DEFAULT_VERSION,
-        )
-        .with_context(|| format!("Failed to connect to Docker socket at {}", config.socket_path))?;
+        let client = open_socket(&config).ok();
+        Ok(Self {
+            client: Mutex::new(client),
+            config,
+            label_warnings: LabelWarnings::default(),
+        })
+    }
+
+    /// Pings Docker and returns its version, e.g. "Docker 27.3.1 (API 1.47)"
+    pub async fn check_connection(&self) -> Result<String> {
+        let client = self.client()?;
+        client.ping().await.context("Docker did not answer the ping")?;
+        let version = client.version().await.context("Failed to get the Docker version")?;
+
+        Ok(format!(
+            "Docker {} (API {})",
+            version.version.as_deref().unwrap_or("?"),
+            version.api_version.as_deref().unwrap_or("?")
+        ))
+    }
 
-        Ok(Self { client })
+    fn client(&self) -> Result<Docker> {
+        let mut client = self.client.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(client) = client.as_ref() {
+            return Ok(client.clone());
+        }
+
+        let opened = open_socket(&self.config)?;
+        *client = Some(opened.clone());
+        Ok(opened)
     }
 
     pub fn new_with_defaults() -> Result<Self> {
         Self::new(DockerClientConfig::default())
     }
+
+    /// Returns the IDs of networks marked internal
+    async fn list_internal_networks(&self) -> Result<HashSet<String>> {
+        let networks = self
+            .client()?
+            .list_networks::<String>(None)
+            .await
+            .context("Failed to list networks")?;
+
+        Ok(networks
+            .into_iter()
+            .filter(|network| network.internal.unwrap_or(false))
+            .filter_map(|network| network.id)
+            .collect())
+    }
+}
+
+fn open_socket(config: &DockerClientConfig) -> Result<Docker> {
+    Docker::connect_with_socket(&config.socket_path, config.timeout_seconds, bollard::API_DEFAULT_VERSION)
+        .with_context(|| format!("Failed to connect to Docker socket at {}", config.socket_path))
+}
+
+/// Returns the addresses of the endpoints selected by the network filter,
+/// or by the container's networks label if it has one
+fn get_ip_addresses(
+    container: &ContainerSummary,
+    internal_networks: &HashSet<String>,
+    config: &DockerClientConfig,
+    warnings: &mut Vec<String>,
+) -> (Vec<Ipv4Addr>, Vec<Ipv6Addr>, Vec<NetworkAttachment>, HashMap<IpAddr, u32>) {
+    let label_filter =
+        get_label(container, NETWORKS_LABEL).and_then(|value| parse_networks_label(container, value, warnings));
+    let filter = label_filter.as_ref().unwrap_or(&config.network_filter);
+
+    let mut endpoints = get_endpoints(container);
+    let all_names = endpoints.iter().map(|endpoint| endpoint.network_name.clone()).collect::<Vec<_>>();
+    let network_names = all_names.iter().map(String::as_str).collect::<HashSet<_>>();
+    endpoints.retain(|endpoint| filter.is_published(endpoint, &network_names, internal_networks));
+
+    let preferred =
+        get_label(container, PREFER_NETWORK_LABEL).and_then(|value| parse_prefer_network_label(container, value, warnings));
+    let rank = |endpoint: &Endpoint| network_rank(endpoint, &network_names, &config.network_priority, preferred.as_ref());
+    order_endpoints(&mut endpoints, rank);
+
+    // Ranks relative to the container's own networks, so that the most preferred address
+    // of every container ranks the same
+    let mut ranks = endpoints.iter().map(rank).collect::<Vec<_>>();
+    ranks.dedup();
+
+    let mut ipv4_addresses = vec![];
+    let mut ipv6_addresses = vec![];
+    let mut networks = vec![];
+    let mut network_ranks = HashMap::new();
+
+    for endpoint in endpoints {
+        let endpoint_rank = rank(&endpoint);
+        let relative_rank = ranks.iter().position(|other| *other == endpoint_rank).unwrap_or_default() as u32;
+        if relative_rank > 0 {
+            network_ranks.extend(endpoint.addresses().map(|ip| (ip, relative_rank)));
+        }
+        ipv4_addresses.extend(endpoint.ipv4);
+        ipv6_addresses.extend(endpoint.ipv6);
+        networks.push(NetworkAttachment {
+            addresses: endpoint.addresses().collect(),
+            subnets: endpoint.subnets,
+        });
+    }
+
+    (ipv4_addresses, ipv6_addresses, networks, network_ranks)
+}
+
+/// Sorts endpoints by rank, then network name.
+/// Docker reports networks in no particular order, so this keeps answers stable between refreshes.
+fn order_endpoints(endpoints: &mut [Endpoint], rank: impl Fn(&Endpoint) -> usize) {
+    endpoints.sort_by_cached_key(|endpoint| (rank(endpoint), endpoint.network_name.clone()));
+}
+
+/// Preference of a network: the container's preferred network, then the priority list, then
+/// all others. The network name order within a rank is not a preference.
+fn network_rank(
+    endpoint: &Endpoint,
+    network_names: &HashSet<&str>,
+    priority: &[NetworkSelector],
+    preferred: Option<&NetworkSelector>,
+) -> usize {
+    if preferred.is_some_and(|selector| selector.matches(endpoint, network_names)) {
+        0
+    } else {
+        priority
+            .iter()
+            .position(|selector| selector.matches(endpoint, network_names))
+            .map_or(usize::MAX, |index| index + 1)
+    }
+}
+
+/// Parses the preferred network label, which is ignored if invalid
+fn parse_prefer_network_label(container: &ContainerSummary, value: &str, warnings: &mut Vec<String>) -> Option<NetworkSelector> {
+    match value.parse() {
+        Ok(selector) => Some(selector),
+        Err(e) => {
+            let container_name = get_names(container).into_iter().next().unwrap_or_default();
+            warnings.push(format!("Container '{}': ignoring label {}: {}", container_name, PREFER_NETWORK_LABEL, e));
+            None
+        }
+    }
+}
+
+/// The networks label replaces the configured filter entirely, internal networks included.
+/// Returns None, so the configured filter applies, if the label selects no network.
+fn parse_networks_label(container: &ContainerSummary, value: &str, warnings: &mut Vec<String>) -> Option<NetworkFilter> {
+    let container_name = get_names(container).into_iter().next().unwrap_or_default();
+    let mut allow = vec![];
+    for selector in value.split(|c: char| c == ',' || c.is_whitespace()).filter(|s| !s.is_empty()) {
+        match selector.parse() {
+            Ok(selector) => allow.push(selector),
+            Err(e) => warnings.push(format!("Container '{}': ignoring label {}: {}", container_name, NETWORKS_LABEL, e)),
+        }
+    }
+
+    if allow.is_empty() {
+        warnings.push(format!(
+            "Container '{}': label {} selects no network, using the configured network filter",
+            container_name, NETWORKS_LABEL
+        ));
+        return None;
+    }
+
+    Some(NetworkFilter {
+        allow,
+        deny: vec![],
+        include_internal: true,
+    })
 }
 
 #[async_trait]
 impl NetworkInfoProvider for DockerClient {
     async fn list_containers_network_info(&self) -> Result<Vec<NetworkInfo>> {
         let containers = self
-            .client
+            .client()?
             .list_containers::<String>(None)
             .await
             .context("Failed to list containers")?;
 
-        let mut result = Vec::new();
+        let internal_networks = if self.config.network_filter.include_internal {
+            HashSet::new()
+        } else {
+            self.list_internal_networks().await?
+        };
 
-        for container in containers {
-            let names = get_names(&container);
+        Ok(network_infos(&containers, &internal_networks, &self.config, &self.label_warnings))
+    }
+}
 
-            let (ipv4_addresses, ipv6_addresses) = get_ip_addresses(container);
+/// Logs label problems once per container, not again on every refresh as long as they persist
+#[derive(Default)]
+pub(crate) struct LabelWarnings {
+    /// The warnings of the last refresh
+    logged: Mutex<HashSet<String>>,
+}
 
-            if !ipv4_addresses.is_empty() || !ipv6_addresses.is_empty() {
-                result.push(NetworkInfo {
-                    names,
-                    ipv4_addresses,
-                    ipv6_addresses,
-                });
-            }
+impl LabelWarnings {
+    /// Logs the warnings of a refresh that weren't logged on the previous one, and returns them
+    fn report(&self, warnings: Vec<String>) -> Vec<String> {
+        let mut logged = self.logged.lock().unwrap_or_else(|e| e.into_inner());
+        let new = warnings.iter().filter(|warning| !logged.contains(*warning)).cloned().collect::<Vec<_>>();
+        for warning in &new {
+            warn!("{}", warning);
         }
-
-        Ok(result)
+        *logged = warnings.into_iter().collect();
+        new
     }
 }
 
-fn get_ip_addresses(container: bollard::secret::ContainerSummary) -> (Vec<Ipv4Addr>, Vec<Ipv6Addr>) {
-    let mut ipv4_addresses = vec![];
-    let mut ipv6_addresses = vec![];
+/// Applies the labels, network filter and naming options to a list of running containers.
+/// `internal_networks` holds the IDs of networks marked internal.
+pub(crate) fn network_infos(
+    containers: &[ContainerSummary],
+    internal_networks: &HashSet<String>,
+    config: &DockerClientConfig,
+    label_warnings: &LabelWarnings,
+) -> Vec<NetworkInfo> {
+    let mut result = Vec::new();
+    let mut warnings = vec![];
 
-    let Some(settings) = container.network_settings else {
-        return (ipv4_addresses, ipv6_addresses);
-    };
+    for container in containers {
+        let mut names = get_names(container);
+        add_unique_names(&mut names, get_aliases(container));
+        let wildcard = get_label(container, WILDCARD_LABEL).is_some_and(is_truthy);
+        let health = container.status.as_deref().map_or(HealthStatus::None, HealthStatus::from_status);
+        let weight = get_label(container, WEIGHT_LABEL).and_then(|value| value.trim().parse().ok());
 
-    let Some(networks_data) = settings.networks else {
-        return (ipv4_addresses, ipv6_addresses);
-    };
+        let (ipv4_addresses, ipv6_addresses, networks, network_ranks) =
+            get_ip_addresses(container, internal_networks, config, &mut warnings);
 
-    for (_, endpoint) in networks_data {
-        if let Some(ipv4) = endpoint.ip_address.as_deref().and_then(parse_ipv4) {
-            ipv4_addresses.push(ipv4);
+        if !ipv4_addresses.is_empty() || !ipv6_addresses.is_empty() {
+            result.push(NetworkInfo {
+                names,
+                ipv4_addresses,
+                ipv6_addresses,
+                networks,
+                wildcard,
+                health,
+                weight,
+                network_ranks,
+            });
         }
+    }
+
+    if config.normalize_names {
+        add_hostname_variants(&mut result);
+    }
+    label_warnings.report(warnings);
 
-        if let Some(ipv6) = endpoint.global_ipv6_address.as_deref().and_then(parse_ipv6) {
-            ipv6_addresses.push(ipv6);
+    result
+}
+
+/// Registers a hostname-safe variant for every name that isn't one already.
+/// The variant of the canonical name becomes the canonical name, so CNAME answers point at it.
+/// Variants colliding with a name of another container are reported and skipped.
+fn add_hostname_variants(infos: &mut [NetworkInfo]) {
+    let mut owners: HashMap<String, usize> = HashMap::new();
+    for (index, info) in infos.iter().enumerate() {
+        for name in &info.names {
+            owners.entry(name.to_ascii_lowercase()).or_insert(index);
         }
     }
 
-    (ipv4_addresses, ipv6_addresses)
+    for index in 0..infos.len() {
+        let variants = infos[index]
+            .names
+            .iter()
+            .enumerate()
+            .filter_map(|(position, name)| to_hostname(name).map(|hostname| (position == 0, name.clone(), hostname)))
+            .collect::<Vec<_>>();
+
+        for (canonical, name, hostname) in variants {
+            match owners.get(&hostname.to_ascii_lowercase()) {
+                Some(&owner) if owner == index => {} // Already valid, or same variant as another name
+                Some(&owner) => {
+                    warn!(
+                        "Hostname '{}' for container name '{}' collides with container '{}'; not registering it",
+                        hostname,
+                        name,
+                        infos[owner].names.first().map(String::as_str).unwrap_or("?")
+                    );
+                }
+                None => {
+                    owners.insert(hostname.to_ascii_lowercase(), index);
+                    if canonical {
+                        infos[index].names.insert(0, hostname);
+                    } else {
+                        infos[index].names.push(hostname);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn get_endpoints(container: &ContainerSummary) -> Vec<Endpoint> {
+    let Some(networks_data) = container
+        .network_settings
+        .as_ref()
+        .and_then(|settings| settings.networks.as_ref())
+    else {
+        return vec![];
+    };
+
+    networks_data
+        .iter()
+        .map(|(network_name, endpoint)| {
+            let ipv4 = endpoint.ip_address.as_deref().and_then(parse_ipv4);
+            let ipv6 = endpoint.global_ipv6_address.as_deref().and_then(parse_ipv6);
+            let subnets = [
+                ipv4.and_then(|ip| to_subnet(ip.into(), endpoint.ip_prefix_len)),
+                ipv6.and_then(|ip| to_subnet(ip.into(), endpoint.global_ipv6_prefix_len)),
+            ];
+
+            Endpoint {
+                network_name: network_name.clone(),
+                network_id: endpoint.network_id.clone().unwrap_or_default(),
+                ipv4,
+                ipv6,
+                subnets: subnets.into_iter().flatten().collect(),
+            }
+        })
+        .collect()
+}
+
+fn to_subnet(ip: IpAddr, prefix_len: Option<i64>) -> Option<IpNet> {
+    let prefix_len = u8::try_from(prefix_len?).ok()?;
+    IpNet::new(ip, prefix_len).ok().map(|subnet| subnet.trunc())
 }
 
 fn parse_ipv4(ip_str: &str) -> Option<Ipv4Addr> {
@@ -128,7 +602,47 @@ fn parse_ipv6(ip_str: &str) -> Option<Ipv6Addr> {
     ip_str.parse::<Ipv6Addr>().ok()
 }
 
-fn get_names(container: &bollard::secret::ContainerSummary) -> Vec<String> {
+fn get_label<'a>(container: &'a ContainerSummary, key: &str) -> Option<&'a str> {
+    container
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get(key))
+        .map(String::as_str)
+}
+
+fn is_truthy(value: &str) -> bool {
+    matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on")
+}
+
+/// Returns the Compose service name and the names from the aliases label
+fn get_aliases(container: &ContainerSummary) -> Vec<String> {
+    let mut aliases = vec![];
+
+    if let Some(service) = get_label(container, COMPOSE_SERVICE_LABEL) {
+        aliases.push(service.to_string());
+    }
+
+    if let Some(names) = get_label(container, ALIASES_LABEL) {
+        aliases.extend(
+            names
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|name| !name.is_empty())
+                .map(str::to_string),
+        );
+    }
+
+    aliases
+}
+
+fn add_unique_names(names: &mut Vec<String>, new_names: Vec<String>) {
+    for name in new_names {
+        if !names.iter().any(|existing| existing.eq_ignore_ascii_case(&name)) {
+            names.push(name);
+        }
+    }
+}
+
+fn get_names(container: &ContainerSummary) -> Vec<String> {
     container
         .names
         .as_ref()
@@ -140,3 +654,262 @@ fn get_names(container: &bollard::secret::ContainerSummary) -> Vec<String> {
         })
         .unwrap_or_default()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network_info(names: &[&str]) -> NetworkInfo {
+        NetworkInfo {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
+            ipv6_addresses: vec![],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn collects_compose_service_and_label_aliases() {
+        let container = ContainerSummary {
+            labels: Some(HashMap::from([
+                (COMPOSE_SERVICE_LABEL.to_string(), "web".to_string()),
+                (ALIASES_LABEL.to_string(), "www, frontend  static".to_string()),
+            ])),
+            ..Default::default()
+        };
+
+        assert_eq!(get_aliases(&container), vec!["web", "www", "frontend", "static"]);
+    }
+
+    #[test]
+    fn adds_only_names_not_already_present() {
+        let mut names = vec!["project-web-1".to_string(), "Web".to_string()];
+
+        add_unique_names(&mut names, vec!["web".to_string(), "www".to_string()]);
+
+        assert_eq!(names, vec!["project-web-1", "Web", "www"]);
+    }
+
+    fn endpoint(network_name: &str, network_id: &str, ipv4: [u8; 4]) -> Endpoint {
+        Endpoint {
+            network_name: network_name.to_string(),
+            network_id: network_id.to_string(),
+            ipv4: Some(Ipv4Addr::from(ipv4)),
+            ipv6: None,
+            subnets: vec![],
+        }
+    }
+
+    #[test]
+    fn parses_network_selectors() {
+        assert_eq!(
+            "frontend".parse::<NetworkSelector>(),
+            Ok(NetworkSelector::NameOrId("frontend".to_string()))
+        );
+        assert_eq!(
+            "172.18.0.0/16".parse::<NetworkSelector>(),
+            Ok(NetworkSelector::Subnet("172.18.0.0/16".parse().unwrap()))
+        );
+        assert!("".parse::<NetworkSelector>().is_err());
+        assert!("10.0.0.0/33".parse::<NetworkSelector>().is_err());
+        assert!("front end".parse::<NetworkSelector>().is_err());
+    }
+
+    #[test]
+    fn parses_startup_policies() {
+        assert_eq!("wait".parse::<StartupPolicy>(), Ok(StartupPolicy::Wait));
+        assert_eq!("Fail".parse::<StartupPolicy>(), Ok(StartupPolicy::Fail));
+        assert!("retry".parse::<StartupPolicy>().is_err());
+    }
+
+    #[test]
+    fn selector_matches_name_id_prefix_and_subnet() {
+        let frontend = endpoint("frontend", "3f2a9c1d5e7b4a60", [172, 18, 0, 5]);
+        let names = HashSet::from(["frontend"]);
+        let matches = |selector: &str| selector.parse::<NetworkSelector>().unwrap().matches(&frontend, &names);
+
+        assert!(matches("frontend"));
+        assert!(matches("3f2a9c1d5e7b"));
+        assert!(matches("3f2a9c1d5e7b4a60"));
+        assert!(matches("172.18.0.0/16"));
+        assert!(!matches("backend"));
+        assert!(!matches("10.0.0.0/8"));
+    }
+
+    #[test]
+    fn selector_takes_names_before_id_prefixes() {
+        let cafe = endpoint("web", "cafe0123456789ab", [172, 18, 0, 5]);
+        let named = endpoint("cafe0123456789", "0a1b2c3d4e5f6789", [172, 19, 0, 5]);
+
+        // Too short for an ID prefix, even if valid hex
+        let names = HashSet::from(["web"]);
+        assert!(!NetworkSelector::NameOrId("cafe".to_string()).matches(&cafe, &names));
+
+        // Names a network of the container, so not taken as another network's ID prefix
+        let names = HashSet::from(["web", "cafe0123456789"]);
+        let selector = NetworkSelector::NameOrId("cafe0123456789".to_string());
+        assert!(selector.matches(&named, &names));
+        assert!(!selector.matches(&cafe, &names));
+        assert!(selector.matches(&cafe, &HashSet::from(["web"])));
+    }
+
+    #[test]
+    fn filter_excludes_internal_networks_by_default() {
+        let internal = HashSet::from(["internal-id".to_string()]);
+        let backend = endpoint("backend", "internal-id", [172, 19, 0, 5]);
+        let frontend = endpoint("frontend", "frontend-id", [172, 18, 0, 5]);
+
+        let filter = NetworkFilter::default();
+        assert!(!filter.is_published(&backend, &HashSet::new(), &internal));
+        assert!(filter.is_published(&frontend, &HashSet::new(), &internal));
+
+        let filter = NetworkFilter {
+            include_internal: true,
+            ..Default::default()
+        };
+        assert!(filter.is_published(&backend, &HashSet::new(), &internal));
+    }
+
+    #[test]
+    fn filter_applies_allow_and_deny_lists() {
+        let none = HashSet::new();
+        let backend = endpoint("backend", "backend-id", [172, 19, 0, 5]);
+        let frontend = endpoint("frontend", "frontend-id", [172, 18, 0, 5]);
+
+        let filter = NetworkFilter {
+            allow: vec![NetworkSelector::Subnet("172.16.0.0/12".parse().unwrap())],
+            deny: vec![NetworkSelector::NameOrId("backend".to_string())],
+            include_internal: false,
+        };
+
+        assert!(filter.is_published(&frontend, &HashSet::new(), &none));
+        assert!(!filter.is_published(&backend, &HashSet::new(), &none));
+        assert!(!filter.is_published(&endpoint("lan", "lan-id", [10, 0, 0, 5]), &HashSet::new(), &none));
+    }
+
+    #[test]
+    fn networks_label_overrides_filter() {
+        let internal = HashSet::from(["internal-id".to_string()]);
+        let container = ContainerSummary::default();
+        let mut warnings = vec![];
+        let filter = parse_networks_label(&container, "backend, 10.0.0.0/8 10.0.0.0/33", &mut warnings).unwrap();
+
+        assert_eq!(filter.allow.len(), 2);
+        assert!(filter.is_published(&endpoint("backend", "internal-id", [172, 19, 0, 5]), &HashSet::new(), &internal));
+        assert!(filter.is_published(&endpoint("lan", "lan-id", [10, 0, 0, 5]), &HashSet::new(), &internal));
+        assert!(!filter.is_published(&endpoint("frontend", "frontend-id", [172, 18, 0, 5]), &HashSet::new(), &internal));
+
+        // A label without a usable selector leaves the configured filter in place
+        assert_eq!(warnings.len(), 1);
+        assert!(parse_networks_label(&container, " , ", &mut warnings).is_none());
+        assert!(parse_networks_label(&container, "10.0.0.0/33", &mut warnings).is_none());
+    }
+
+    #[test]
+    fn logs_label_warnings_once_while_they_persist() {
+        let container = ContainerSummary {
+            names: Some(vec!["/web".to_string()]),
+            ..Default::default()
+        };
+        let label_warnings = LabelWarnings::default();
+        let refresh = |value: &str| {
+            let mut warnings = vec![];
+            parse_prefer_network_label(&container, value, &mut warnings);
+            label_warnings.report(warnings)
+        };
+
+        assert_eq!(
+            refresh("front end"),
+            vec!["Container 'web': ignoring label docker-dns.prefer-network: invalid network name, ID or subnet 'front end'"]
+        );
+        assert!(refresh("front end").is_empty());
+        assert!(refresh("frontend").is_empty());
+        assert_eq!(refresh("front end").len(), 1);
+    }
+
+    fn network_names(endpoints: &[Endpoint]) -> Vec<&str> {
+        endpoints.iter().map(|endpoint| endpoint.network_name.as_str()).collect()
+    }
+
+    #[test]
+    fn orders_endpoints_by_network_name() {
+        let mut endpoints = vec![
+            endpoint("frontend", "1", [172, 18, 0, 5]),
+            endpoint("backend", "2", [172, 19, 0, 5]),
+            endpoint("bridge", "3", [172, 17, 0, 5]),
+        ];
+
+        order_endpoints(&mut endpoints, |endpoint| network_rank(endpoint, &HashSet::new(), &[], None));
+
+        assert_eq!(network_names(&endpoints), vec!["backend", "bridge", "frontend"]);
+    }
+
+    #[test]
+    fn orders_endpoints_by_priority_then_preferred_label() {
+        let mut endpoints = vec![
+            endpoint("backend", "1", [172, 19, 0, 5]),
+            endpoint("bridge", "2", [172, 17, 0, 5]),
+            endpoint("frontend", "3", [172, 18, 0, 5]),
+        ];
+        let priority = vec![
+            NetworkSelector::NameOrId("frontend".to_string()),
+            NetworkSelector::Subnet("172.17.0.0/16".parse().unwrap()),
+        ];
+
+        order_endpoints(&mut endpoints, |endpoint| network_rank(endpoint, &HashSet::new(), &priority, None));
+        assert_eq!(network_names(&endpoints), vec!["frontend", "bridge", "backend"]);
+
+        let preferred = NetworkSelector::NameOrId("backend".to_string());
+        order_endpoints(&mut endpoints, |endpoint| network_rank(endpoint, &HashSet::new(), &priority, Some(&preferred)));
+        assert_eq!(network_names(&endpoints), vec!["backend", "frontend", "bridge"]);
+    }
+
+    #[test]
+    fn parses_health_from_container_status() {
+        assert_eq!(HealthStatus::from_status("Up 5 minutes"), HealthStatus::None);
+        assert_eq!(HealthStatus::from_status("Up 5 seconds (health: starting)"), HealthStatus::Starting);
+        assert_eq!(HealthStatus::from_status("Up 5 minutes (healthy)"), HealthStatus::Healthy);
+        assert_eq!(HealthStatus::from_status("Up 5 minutes (unhealthy)"), HealthStatus::Unhealthy);
+    }
+
+    #[test]
+    fn derives_subnet_from_address_and_prefix_length() {
+        let ip = IpAddr::from([172, 18, 0, 5]);
+
+        assert_eq!(to_subnet(ip, Some(16)), Some("172.18.0.0/16".parse().unwrap()));
+        assert_eq!(to_subnet(ip, Some(0)), Some("0.0.0.0/0".parse().unwrap()));
+        assert_eq!(to_subnet(ip, Some(33)), None);
+        assert_eq!(to_subnet(ip, None), None);
+    }
+
+    #[test]
+    fn adds_hostname_variant_for_invalid_names() {
+        let mut infos = vec![network_info(&["zen_montalcini"]), network_info(&["nginx", "web_server"])];
+
+        add_hostname_variants(&mut infos);
+
+        // The canonical name's variant takes its place, so CNAMEs point at a valid hostname
+        assert_eq!(infos[0].names, vec!["zen-montalcini", "zen_montalcini"]);
+        assert_eq!(infos[1].names, vec!["nginx", "web_server", "web-server"]);
+    }
+
+    #[test]
+    fn skips_hostname_variant_colliding_with_other_container() {
+        let mut infos = vec![network_info(&["my_app"]), network_info(&["my-app"])];
+
+        add_hostname_variants(&mut infos);
+
+        assert_eq!(infos[0].names, vec!["my_app"]);
+        assert_eq!(infos[1].names, vec!["my-app"]);
+    }
+
+    #[test]
+    fn first_container_wins_when_variants_collide() {
+        let mut infos = vec![network_info(&["my_app"]), network_info(&["my_app!"])];
+
+        add_hostname_variants(&mut infos);
+
+        assert_eq!(infos[0].names, vec!["my-app", "my_app"]);
+        assert_eq!(infos[1].names, vec!["my_app!"]);
+    }
+}
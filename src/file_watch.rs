@@ -0,0 +1,44 @@
+use anyhow::{Context, Result};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::path::Path;
+use tokio::io::unix::AsyncFd;
+
+/// Calls `on_change` whenever the file is written or replaced. Watches the directory,
+/// so editors that save by renaming a new file over the old one are noticed too.
+pub async fn watch_file(path: &Path, mut on_change: impl FnMut()) -> Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{} has no file name", path.display()))?;
+
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC).context("Failed to set up inotify")?;
+    inotify
+        .add_watch(directory, AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO)
+        .with_context(|| format!("Failed to watch {}", directory.display()))?;
+    let inotify = AsyncFd::new(InotifyFd(inotify))?;
+
+    loop {
+        let mut ready = inotify.readable().await?;
+        let events = match ready.try_io(|inotify| inotify.get_ref().0.read_events().map_err(io::Error::from)) {
+            Ok(events) => events.context("Failed to read inotify events")?,
+            Err(_would_block) => continue,
+        };
+        if events.iter().any(|event| event.name.as_deref() == Some(file_name)) {
+            on_change();
+        }
+    }
+}
+
+/// AsyncFd needs AsRawFd, which nix's Inotify only provides through AsFd
+struct InotifyFd(Inotify);
+
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
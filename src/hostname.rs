@@ -0,0 +1,102 @@
+/// Maximum length of a single DNS label (RFC 1035)
+const MAX_LABEL_LEN: usize = 63;
+
+/// Maximum length of a full DNS name in text form, without the trailing dot (RFC 1035)
+const MAX_NAME_LEN: usize = 253;
+
+/// Converts a container name into a hostname-safe name
+///
+/// Underscores become hyphens, characters not allowed in hostnames are stripped,
+/// Unicode labels are punycode encoded and labels over 63 characters are truncated.
+/// Returns None if any label has nothing usable left, rather than dropping it and
+/// answering a different name.
+pub fn to_hostname(name: &str) -> Option<String> {
+    let labels = name.split('.').map(to_hostname_label).collect::<Option<Vec<_>>>()?;
+
+    let hostname = labels.join(".");
+    if hostname.len() > MAX_NAME_LEN {
+        return None;
+    }
+
+    Some(hostname)
+}
+
+fn to_hostname_label(label: &str) -> Option<String> {
+    let label = label.replace('_', "-");
+
+    let label = if label.is_ascii() {
+        label
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect::<String>()
+    } else {
+        // Punycode can't be truncated without corrupting it, so overlong results are dropped
+        let encoded = idna::domain_to_ascii(&label).ok()?;
+        if encoded.len() > MAX_LABEL_LEN {
+            return None;
+        }
+        encoded
+    };
+
+    let mut label = label.trim_matches('-').to_string();
+    if label.len() > MAX_LABEL_LEN {
+        label.truncate(MAX_LABEL_LEN);
+        label = label.trim_end_matches('-').to_string();
+    }
+
+    if label.is_empty() {
+        None
+    } else {
+        Some(label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_hostname;
+
+    #[test]
+    fn replaces_underscores_with_hyphens() {
+        assert_eq!(to_hostname("zen_montalcini"), Some("zen-montalcini".to_string()));
+        assert_eq!(to_hostname("project_web_1"), Some("project-web-1".to_string()));
+    }
+
+    #[test]
+    fn keeps_valid_hostnames_unchanged() {
+        assert_eq!(to_hostname("nginx"), Some("nginx".to_string()));
+        assert_eq!(to_hostname("app.production"), Some("app.production".to_string()));
+    }
+
+    #[test]
+    fn strips_invalid_characters() {
+        assert_eq!(to_hostname("my+app!"), Some("myapp".to_string()));
+        assert_eq!(to_hostname("_leading_"), Some("leading".to_string()));
+        assert_eq!(to_hostname("___"), None);
+    }
+
+    #[test]
+    fn rejects_names_with_unusable_labels() {
+        assert_eq!(to_hostname("a..b"), None);
+        assert_eq!(to_hostname("web.___"), None);
+        assert_eq!(to_hostname(".web"), None);
+
+        // Too long once punycode encoded
+        let name = format!("web.{}", "ü".repeat(60));
+        assert_eq!(to_hostname(&name), None);
+    }
+
+    #[test]
+    fn truncates_long_labels() {
+        let name = format!("{}_x", "a".repeat(62));
+        assert_eq!(to_hostname(&name), Some("a".repeat(62)));
+
+        let name = "b".repeat(100);
+        assert_eq!(to_hostname(&name), Some("b".repeat(63)));
+    }
+
+    #[test]
+    fn punycode_encodes_unicode_labels() {
+        assert_eq!(to_hostname("bücher"), Some("xn--bcher-kva".to_string()));
+        assert_eq!(to_hostname("æøå_app"), Some("xn---app-poaf0k".to_string()));
+    }
+}
@@ -0,0 +1,120 @@
+use crate::file_watch::watch_file;
+use crate::resolver::{DnsResolver, DnsResponse, StaticResolver};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::{error, info};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Names from an /etc/hosts style file, reloaded when the file changes
+pub struct HostsFileResolver {
+    path: PathBuf,
+    hosts: RwLock<Arc<StaticResolver>>,
+}
+
+impl HostsFileResolver {
+    /// Reads the file. Fails if it cannot be read or parsed.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let hosts = read_hosts_file(&path)?;
+        Ok(Self {
+            path,
+            hosts: RwLock::new(Arc::new(hosts)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of names currently loaded
+    pub fn len(&self) -> usize {
+        self.current().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current().is_empty()
+    }
+
+    /// Reads the file again. On error the current names are kept.
+    pub fn reload(&self) -> Result<()> {
+        let hosts = read_hosts_file(&self.path)?;
+        *self.hosts.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(hosts);
+        Ok(())
+    }
+
+    /// Reloads the file whenever it is written or replaced
+    pub async fn watch(self: Arc<Self>) -> Result<()> {
+        watch_file(&self.path, || match self.reload() {
+            Ok(()) => info!("Reloaded {}: {} names", self.path.display(), self.len()),
+            Err(e) => error!("Keeping the previous hosts: {:#}", e),
+        })
+        .await
+    }
+
+    fn current(&self) -> Arc<StaticResolver> {
+        Arc::clone(&self.hosts.read().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+#[async_trait]
+impl DnsResolver for HostsFileResolver {
+    async fn resolve(&self, domain: &str) -> Option<Arc<DnsResponse>> {
+        self.current().resolve(domain).await
+    }
+}
+
+fn read_hosts_file(path: &Path) -> Result<StaticResolver> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    StaticResolver::from_hosts(&content).with_context(|| format!("Invalid hosts file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+
+    #[tokio::test]
+    async fn keeps_previous_hosts_on_bad_reload() {
+        let dir = TempDir::new("hosts-reload");
+        let path = dir.join("hosts");
+        std::fs::write(&path, "10.0.0.1 db\n").unwrap();
+        let resolver = HostsFileResolver::load(&path).unwrap();
+
+        std::fs::write(&path, "10.0.0.1 db\nnot-an-address cache\n").unwrap();
+        let error = resolver.reload().unwrap_err();
+
+        assert!(format!("{:#}", error).contains("line 2: invalid address 'not-an-address'"));
+        assert!(resolver.resolve("db").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn reloads_when_file_is_replaced() {
+        let dir = TempDir::new("hosts-watch");
+        let path = dir.join("hosts");
+        std::fs::write(&path, "10.0.0.1 db\n").unwrap();
+        let resolver = Arc::new(HostsFileResolver::load(&path).unwrap());
+        let watcher = tokio::spawn(Arc::clone(&resolver).watch());
+        tokio::task::yield_now().await;
+
+        // Like an editor: write a new file and rename it over the old one
+        let new_path = dir.join("hosts.new");
+        std::fs::write(&new_path, "10.0.0.2 db\n").unwrap();
+        std::fs::rename(&new_path, &path).unwrap();
+
+        let reloaded = async {
+            loop {
+                let db = resolver.resolve("db").await.unwrap();
+                if db.ipv4_addresses == vec![Ipv4Addr::new(10, 0, 0, 2)] {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), reloaded).await.unwrap();
+        watcher.abort();
+    }
+}
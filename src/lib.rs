@@ -2,4 +2,22 @@ pub mod resolver;
 pub mod server;
 pub mod custom_handler;
 pub mod docker_client;
-pub mod strip_prefix_sane;
\ No newline at end of file
+pub mod strip_prefix_sane;
+pub mod hostname;
+pub mod routes;
+pub mod answer_order;
+pub mod access_control;
+pub mod metrics;
+pub mod log_throttle;
+pub mod rate_limit;
+pub mod tls;
+pub mod bind;
+pub mod systemd;
+pub mod hosts_file;
+pub mod file_watch;
+pub mod zone_file;
+pub mod container_file;
+pub mod snapshot;
+pub mod circuit_breaker;
+#[cfg(test)]
+mod test_util;
@@ -0,0 +1,55 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits how often a recurring message is logged, counting the suppressed ones
+pub struct LogThrottle {
+    interval: Duration,
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Default)]
+struct ThrottleState {
+    last_logged: Option<Instant>,
+    suppressed: u64,
+}
+
+impl LogThrottle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            state: Mutex::new(ThrottleState::default()),
+        }
+    }
+
+    /// Returns the number of messages suppressed since the last logged one
+    /// if this message should be logged, or None if it should be suppressed
+    pub fn check(&self) -> Option<u64> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        if state.last_logged.is_some_and(|instant| instant.elapsed() < self.interval) {
+            state.suppressed += 1;
+            return None;
+        }
+
+        state.last_logged = Some(Instant::now());
+        Some(std::mem::take(&mut state.suppressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogThrottle;
+    use std::time::Duration;
+
+    #[test]
+    fn suppresses_messages_within_interval() {
+        let throttle = LogThrottle::new(Duration::from_millis(50));
+
+        assert_eq!(throttle.check(), Some(0));
+        assert_eq!(throttle.check(), None);
+        assert_eq!(throttle.check(), None);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(throttle.check(), Some(2));
+    }
+}
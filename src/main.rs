@@ -1,28 +1,66 @@
-use clap::Parser;
-use docker_dns::docker_client::{DockerClient, DockerClientConfig};
-use docker_dns::resolver::{DockerResolver, DockerResolverConfig};
+use clap::{ArgGroup, Parser};
+use docker_dns::access_control::{AccessControl, ZoneEntry};
+use docker_dns::answer_order::AnswerOrder;
+use docker_dns::bind::BindAddress;
+use docker_dns::circuit_breaker::BackoffConfig;
+use docker_dns::container_file::ContainerFile;
+use docker_dns::custom_handler::CustomHandlerConfig;
+use docker_dns::docker_client::{DockerClient, DockerClientConfig, NetworkFilter, NetworkSelector, StartupPolicy};
+use docker_dns::file_watch::watch_file;
+use docker_dns::hosts_file::HostsFileResolver;
+use docker_dns::metrics::Metrics;
+use docker_dns::rate_limit::RateLimitConfig;
+use docker_dns::resolver::{
+    ChainPolicy, ChainResolver, DnsResolver, DockerResolver, DockerResolverConfig, HealthPolicy, HostEntry,
+    SplitHorizon, StaticResolver,
+};
+use docker_dns::routes::Reachability;
 use docker_dns::server::DnsServer;
+use docker_dns::systemd;
+use docker_dns::tls::TlsConfig;
+use docker_dns::zone_file::ZoneFile;
 use env_logger::Builder;
-use log::LevelFilter;
+use ipnet::IpNet;
+use log::{LevelFilter, warn};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::signal;
+use tokio::signal::unix::{SignalKind, signal as unix_signal};
 
 /// DNS server that resolves Docker container names to their IP addresses.
 /// Source: https://github.com/jvcdk/docker-dns
 #[derive(Parser, Debug)]
 #[command(name = "docker-dns")]
 #[command(author, version, about, long_about = None)]
+#[command(group(ArgGroup::new("tls_listener").args(["dot_bind", "doh_bind"]).multiple(true)))]
 struct Args {
-    /// DNS server bind address.
-    #[arg(short, long, default_value = "0.0.0.0:53")]
-    bind: String,
+    /// DNS server bind address, or an interface name and port (e.g. "docker0:53") to listen on
+    /// all addresses of the interface, following them as they change. Can be repeated.
+    #[arg(short, long, value_name = "ADDR", default_value = "0.0.0.0:53")]
+    bind: Vec<BindAddress>,
+
+    /// UDP sockets per bind address. More than one uses SO_REUSEPORT to spread queries over
+    /// several cores.
+    #[arg(long, default_value = "1")]
+    udp_sockets: usize,
 
     /// Docker socket path.
     #[arg(short, long, default_value = "/var/run/docker.sock")]
     socket: String,
 
+    /// What to do if Docker doesn't answer at startup: "wait" (answer SERVFAIL for unknown names
+    /// until the first sync, reconnecting in the background) or "fail" (exit with code 69)
+    #[arg(long, value_name = "POLICY", default_value = "wait")]
+    docker_startup: StartupPolicy,
+
+    /// Read the containers from this file instead of Docker: `docker inspect` output, the Docker
+    /// API container list, or docker-dns' own JSON format (see the README). The naming and
+    /// network options still apply. Reloaded when the file changes.
+    #[arg(long, value_name = "PATH")]
+    containers_file: Option<PathBuf>,
+
     /// Cache hit timeout in seconds (how long to cache successful lookups).
     #[arg(long, default_value = "60")]
     hit_timeout: u64,
@@ -40,8 +78,174 @@ struct Args {
     /// The suffix will be stripped before looking up container names.
     #[arg(long, default_value = "")]
     suffix: String,
+
+    /// Also register hostname-safe variants of container names
+    /// (underscores to hyphens, invalid characters stripped, Unicode punycode encoded).
+    #[arg(long)]
+    normalize_names: bool,
+
+    /// Resolve subdomains of container names to the container (e.g. "anything.web" to "web").
+    /// Individual containers can opt in with the label "docker-dns.wildcard=true".
+    #[arg(long)]
+    wildcard: bool,
+
+    /// Answer aliases (Compose service names and names from the "docker-dns.aliases" label)
+    /// with a CNAME to the container's canonical name.
+    #[arg(long)]
+    cname_aliases: bool,
+
+    /// Only publish addresses on this network (name, ID or subnet CIDR). Can be repeated.
+    /// Containers can override the network filter with the label "docker-dns.networks".
+    #[arg(long = "allow-network", value_name = "NETWORK")]
+    allow_networks: Vec<NetworkSelector>,
+
+    /// Never publish addresses on this network (name, ID or subnet CIDR). Can be repeated.
+    #[arg(long = "deny-network", value_name = "NETWORK")]
+    deny_networks: Vec<NetworkSelector>,
+
+    /// Also publish addresses on networks marked internal.
+    #[arg(long)]
+    include_internal_networks: bool,
+
+    /// Return addresses on this network (name, ID or subnet CIDR) first. Can be repeated, in priority order.
+    /// Other networks follow in name order. Containers can override it with the label "docker-dns.prefer-network".
+    #[arg(long = "prefer-network", value_name = "NETWORK")]
+    prefer_networks: Vec<NetworkSelector>,
+
+    /// Only return the top-priority IPv4 and IPv6 address of each container, chosen after
+    /// the split-horizon and reachability filters.
+    #[arg(long)]
+    single_address: bool,
+
+    /// Tailor answers to the querying client's network: "off", "prefer" (client's network first)
+    /// or "filter" (only the client's network). Clients on the host get all addresses.
+    #[arg(long, default_value = "off")]
+    split_horizon: SplitHorizon,
+
+    /// Handle addresses the host has no route to (default route excluded): "off", "deprioritize"
+    /// (return them last) or "drop". The routing table is re-read on every cache refresh.
+    #[arg(long, default_value = "off")]
+    reachability: Reachability,
+
+    /// How container health affects answers: "ignore", "exclude-unhealthy", "prefer-healthy" (healthy first)
+    /// or "require-healthy" (also holds back starting containers). If every container behind a name
+    /// is unhealthy, they are all returned.
+    #[arg(long, default_value = "ignore")]
+    health_policy: HealthPolicy,
+
+    /// Order of the addresses when a name has several: "fixed", "round-robin", "random" or "weighted"
+    /// (by the label "docker-dns.weight", default weight 100). Only reorders addresses of equal preference:
+    /// split horizon "prefer", "prefer-healthy", reachability "deprioritize" and preferred networks still come first.
+    #[arg(long, default_value = "fixed")]
+    answer_order: AnswerOrder,
+
+    /// Answer a name (without the suffix) with a fixed address, given as "<name>=<ip>"
+    /// (e.g. "db=192.168.1.10"). Can be repeated.
+    #[arg(long = "host", value_name = "NAME=IP")]
+    hosts: Vec<HostEntry>,
+
+    /// Answer names (without the suffix) from a file in /etc/hosts format. The file is reloaded
+    /// when it changes; if it then cannot be parsed, the previous contents are kept.
+    #[arg(long, value_name = "PATH")]
+    hosts_file: Option<PathBuf>,
+
+    /// Also answer records (SRV, TXT, MX, CNAME, ...) from a zone file in RFC 1035 master format.
+    /// Relative names are relative to the suffix, and names outside it are rejected. Zone records take
+    /// precedence over containers for their name and type, and a CNAME takes over its whole name
+    /// (it can't have other records). Reloaded when the file changes.
+    #[arg(long, value_name = "PATH")]
+    zone_file: Option<PathBuf>,
+
+    /// How --host and --hosts-file entries combine with containers of the same name: "override"
+    /// (a static address replaces the container's addresses of the same family), "first-match"
+    /// (only the static addresses) or "merge" (both, static first). --host entries come before
+    /// the hosts file.
+    #[arg(long, default_value = "override")]
+    host_policy: ChainPolicy,
+
+    /// Only answer clients in this subnet (CIDR). Can be repeated.
+    #[arg(long = "allow-client", value_name = "CIDR")]
+    allow_clients: Vec<IpNet>,
+
+    /// Never answer clients in this subnet (CIDR). Can be repeated.
+    #[arg(long = "deny-client", value_name = "CIDR")]
+    deny_clients: Vec<IpNet>,
+
+    /// Only answer clients in the subnet for names in the zone, given as "<zone>=<cidr>"
+    /// (e.g. "internal.docker=127.0.0.0/8"). Can be repeated.
+    #[arg(long = "zone-allow-client", value_name = "ZONE=CIDR")]
+    zone_allow_clients: Vec<ZoneEntry>,
+
+    /// Never answer clients in the subnet for names in the zone, given as "<zone>=<cidr>". Can be repeated.
+    #[arg(long = "zone-deny-client", value_name = "ZONE=CIDR")]
+    zone_deny_clients: Vec<ZoneEntry>,
+
+    /// Silently drop queries from denied clients instead of answering REFUSED.
+    #[arg(long)]
+    drop_denied: bool,
+
+    /// Maximum responses per second to a client network (/24 for IPv4, /56 for IPv6) over UDP.
+    /// 0 disables response rate limiting.
+    #[arg(long, default_value = "0")]
+    rate_limit: u32,
+
+    /// Maximum NXDOMAIN responses per second to a client network over UDP. 0 disables the limit.
+    #[arg(long, default_value = "0")]
+    nxdomain_rate_limit: u32,
+
+    /// Send every nth rate limited response truncated, so real clients retry over TCP,
+    /// and drop the others. 0 drops all rate limited responses.
+    #[arg(long, default_value = "2")]
+    rate_limit_slip: u32,
+
+    /// Maximum Docker refreshes per minute triggered by queries for unknown names. 0 means no limit.
+    #[arg(long, default_value = "0")]
+    max_miss_refreshes: u32,
+
+    /// Longest pause between Docker refreshes after repeated failures. Refreshes are paused after
+    /// 3 failures in a row, for 2 seconds at first and twice as long after every failed retry.
+    #[arg(long, value_name = "SECONDS", default_value = "60")]
+    max_retry_interval: u64,
+
+    /// Save the names to this file whenever they change, and serve them from it at startup until
+    /// Docker answers (e.g. /var/lib/docker-dns/snapshot.json). Unusable snapshots are discarded.
+    #[arg(long, value_name = "PATH")]
+    snapshot_file: Option<PathBuf>,
+
+    /// Seconds after saving that names from the snapshot file are no longer served. Until Docker
+    /// confirms them, they are answered with a TTL of at most 30 seconds.
+    #[arg(long, value_name = "SECONDS", default_value = "86400", requires = "snapshot_file")]
+    snapshot_max_age: u64,
+
+    /// Serve DNS-over-TLS on this address (e.g. "0.0.0.0:853"). Requires --tls-cert and --tls-key.
+    #[arg(long, value_name = "ADDR", requires_all = ["tls_cert", "tls_key"])]
+    dot_bind: Option<SocketAddr>,
+
+    /// Serve DNS-over-HTTPS on this address, at the path /dns-query (e.g. "0.0.0.0:443").
+    /// Requires --tls-cert and --tls-key.
+    #[arg(long, value_name = "ADDR", requires_all = ["tls_cert", "tls_key"])]
+    doh_bind: Option<SocketAddr>,
+
+    /// Only answer DNS-over-HTTPS requests for this host name.
+    #[arg(long, requires = "doh_bind")]
+    doh_hostname: Option<String>,
+
+    /// PEM certificate chain for DNS-over-TLS and DNS-over-HTTPS. Reloaded on SIGHUP.
+    #[arg(long, value_name = "PATH", requires = "tls_listener")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for DNS-over-TLS and DNS-over-HTTPS. Reloaded on SIGHUP.
+    #[arg(long, value_name = "PATH", requires = "tls_listener")]
+    tls_key: Option<PathBuf>,
+
+    /// Seconds to let queries and Docker refreshes in progress finish on SIGTERM or Ctrl+C
+    #[arg(long, value_name = "SECONDS", default_value = "5")]
+    shutdown_timeout: u64,
 }
 
+/// Exit code with `--docker-startup fail` when Docker doesn't answer (EX_UNAVAILABLE)
+const EXIT_DOCKER_UNAVAILABLE: i32 = 69;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
@@ -63,8 +267,17 @@ async fn main() -> anyhow::Result<()> {
     // Print configuration to stdout (always visible)
     println!("Docker DNS Server v{}", env!("CARGO_PKG_VERSION"));
     println!("Configuration:");
-    println!("  Bind address: {}", args.bind);
-    println!("  Docker socket: {}", args.socket);
+    for bind in &args.bind {
+        println!("  Bind address: {}", bind);
+    }
+    println!("  UDP sockets per address: {}", args.udp_sockets);
+    match &args.containers_file {
+        Some(path) => println!("  Containers file: {}", path.display()),
+        None => {
+            println!("  Docker socket: {}", args.socket);
+            println!("  Docker startup: {:?}", args.docker_startup);
+        }
+    }
     println!("  Hit timeout: {}s", args.hit_timeout);
     println!("  Miss timeout: {}s", args.miss_timeout);
     println!("  Docker timeout: {}s", args.docker_timeout);
@@ -73,6 +286,65 @@ async fn main() -> anyhow::Result<()> {
     } else {
         println!("  DNS suffix: {}", suffix);
     }
+    println!("  Normalize names: {}", args.normalize_names);
+    println!("  Wildcard: {}", args.wildcard);
+    println!("  CNAME aliases: {}", args.cname_aliases);
+    if !args.allow_networks.is_empty() {
+        println!("  Allowed networks: {:?}", args.allow_networks);
+    }
+    if !args.deny_networks.is_empty() {
+        println!("  Denied networks: {:?}", args.deny_networks);
+    }
+    println!("  Include internal networks: {}", args.include_internal_networks);
+    if !args.prefer_networks.is_empty() {
+        println!("  Preferred networks: {:?}", args.prefer_networks);
+    }
+    println!("  Single address: {}", args.single_address);
+    println!("  Split horizon: {:?}", args.split_horizon);
+    println!("  Reachability: {:?}", args.reachability);
+    println!("  Health policy: {:?}", args.health_policy);
+    println!("  Answer order: {:?}", args.answer_order);
+    for host in &args.hosts {
+        println!("  Host: {} = {}", host.name, host.ip);
+    }
+    if let Some(path) = &args.hosts_file {
+        println!("  Hosts file: {}", path.display());
+    }
+    if let Some(path) = &args.zone_file {
+        println!("  Zone file: {}", path.display());
+    }
+    if !args.hosts.is_empty() || args.hosts_file.is_some() {
+        println!("  Host policy: {:?}", args.host_policy);
+    }
+    if !args.allow_clients.is_empty() {
+        println!("  Allowed clients: {:?}", args.allow_clients);
+    }
+    if !args.deny_clients.is_empty() {
+        println!("  Denied clients: {:?}", args.deny_clients);
+    }
+    for entry in &args.zone_allow_clients {
+        println!("  Allowed clients for {}: {}", entry.zone, entry.subnet);
+    }
+    for entry in &args.zone_deny_clients {
+        println!("  Denied clients for {}: {}", entry.zone, entry.subnet);
+    }
+    println!("  Drop denied: {}", args.drop_denied);
+    println!(
+        "  Rate limit: {}/s (NXDOMAIN: {}/s, slip: {})",
+        args.rate_limit, args.nxdomain_rate_limit, args.rate_limit_slip
+    );
+    println!("  Max miss refreshes: {}/min", args.max_miss_refreshes);
+    println!("  Max retry interval: {}s", args.max_retry_interval);
+    if let Some(path) = &args.snapshot_file {
+        println!("  Snapshot file: {}", path.display());
+    }
+    if let Some(addr) = args.dot_bind {
+        println!("  DNS-over-TLS: {}", addr);
+    }
+    if let Some(addr) = args.doh_bind {
+        println!("  DNS-over-HTTPS: https://{}/dns-query", addr);
+    }
+    println!("  Shutdown timeout: {}s", args.shutdown_timeout);
     println!();
 
 
@@ -80,32 +352,190 @@ async fn main() -> anyhow::Result<()> {
     let docker_config = DockerClientConfig {
         socket_path: args.socket,
         timeout_seconds: args.docker_timeout,
+        normalize_names: args.normalize_names,
+        network_filter: NetworkFilter {
+            allow: args.allow_networks,
+            deny: args.deny_networks,
+            include_internal: args.include_internal_networks,
+        },
+        network_priority: args.prefer_networks,
     };
-    let docker_client = DockerClient::new(docker_config)?;
-    println!("✓ Connected to Docker daemon");
-
     // Create DNS resolver with caching
     let resolver_config = DockerResolverConfig {
         hit_timeout: Duration::from_secs(args.hit_timeout),
         miss_timeout: Duration::from_secs(args.miss_timeout),
         refresh_timeout: Duration::from_secs(args.docker_timeout),
+        wildcard: args.wildcard,
+        single_address: args.single_address,
+        split_horizon: args.split_horizon,
+        reachability: args.reachability,
+        health_policy: args.health_policy,
+        max_miss_refreshes_per_minute: args.max_miss_refreshes,
+        snapshot_path: args.snapshot_file.clone(),
+        snapshot_max_age: Duration::from_secs(args.snapshot_max_age),
+        backoff: BackoffConfig {
+            max_delay: Duration::from_secs(args.max_retry_interval),
+            ..Default::default()
+        },
+    };
+    // Shared by the resolver and the server, so the shutdown summary covers both
+    let metrics = Arc::new(Metrics::default());
+    let resolver = match &args.containers_file {
+        Some(path) => {
+            let resolver = DockerResolver::new(ContainerFile::new(path, docker_config), resolver_config)
+                .with_metrics(Arc::clone(&metrics));
+            let resolver = Arc::new(resolver);
+            let refresher = Arc::clone(&resolver);
+            let path = path.clone();
+            tokio::spawn(async move {
+                let reload = || {
+                    let resolver = Arc::clone(&refresher);
+                    // Failures are logged by the resolver
+                    tokio::spawn(async move { resolver.force_refresh().await });
+                };
+                watch_for_changes(path.clone(), watch_file(&path, reload)).await
+            });
+            resolver
+        }
+        None => {
+            let docker_client = DockerClient::new(docker_config)?;
+            match docker_client.check_connection().await {
+                Ok(version) => println!("✓ Connected to {}", version),
+                Err(e) if args.docker_startup == StartupPolicy::Fail => {
+                    eprintln!("✗ Docker is not reachable: {:#}", e);
+                    std::process::exit(EXIT_DOCKER_UNAVAILABLE);
+                }
+                Err(e) => println!("✗ Docker is not reachable yet, answering SERVFAIL until it is: {:#}", e),
+            }
+            Arc::new(DockerResolver::new(docker_client, resolver_config).with_metrics(Arc::clone(&metrics)))
+        }
     };
-    let resolver = DockerResolver::new(docker_client, resolver_config);
     println!("✓ DNS resolver initialized");
 
+    // Tell systemd when the first Docker sync is done, and keep its status up to date
+    tokio::spawn(systemd::notify_sync_status(resolver.sync_status()));
+    let retry_interval = Duration::from_secs(args.miss_timeout).max(Duration::from_secs(1));
+    tokio::spawn(initial_sync(Arc::clone(&resolver), retry_interval));
+
+    // Static --host and --hosts-file entries take precedence over containers
+    let mut resolvers: Vec<Arc<dyn DnsResolver>> = vec![];
+    if !args.hosts.is_empty() {
+        let mut hosts = StaticResolver::new();
+        for host in &args.hosts {
+            hosts.add_mapping(host.name.as_str(), host.ip);
+        }
+        resolvers.push(Arc::new(hosts));
+    }
+    if let Some(path) = &args.hosts_file {
+        let hosts_file = Arc::new(HostsFileResolver::load(path)?);
+        println!("✓ Loaded {} names from {}", hosts_file.len(), path.display());
+        tokio::spawn(watch_for_changes(path.clone(), Arc::clone(&hosts_file).watch()));
+        resolvers.push(hosts_file);
+    }
+    let resolver: Arc<dyn DnsResolver> = if resolvers.is_empty() {
+        resolver
+    } else {
+        resolvers.push(resolver);
+        Arc::new(ChainResolver::new(resolvers, args.host_policy))
+    };
+
     // Parse bind address and start DNS server
-    let addr: SocketAddr = args.bind.parse()?;
-    let ttl = args.hit_timeout as u32;
-    let server = DnsServer::new(Arc::new(resolver), addr, suffix, ttl);
+    let mut access_control = AccessControl {
+        allow: args.allow_clients,
+        deny: args.deny_clients,
+        zones: vec![],
+        drop_denied: args.drop_denied,
+    };
+    for entry in &args.zone_allow_clients {
+        access_control.add_zone_entry(entry, true);
+    }
+    for entry in &args.zone_deny_clients {
+        access_control.add_zone_entry(entry, false);
+    }
+
+    let zone_file = match &args.zone_file {
+        Some(path) => {
+            let zone_file = Arc::new(ZoneFile::load(path, &suffix)?);
+            println!("✓ Loaded {} records from {}", zone_file.len(), path.display());
+            tokio::spawn(watch_for_changes(path.clone(), Arc::clone(&zone_file).watch()));
+            Some(zone_file)
+        }
+        None => None,
+    };
+
+    let handler_config = CustomHandlerConfig {
+        suffix,
+        ttl: args.hit_timeout as u32,
+        cname_aliases: args.cname_aliases,
+        answer_order: args.answer_order,
+        access_control,
+        rate_limit: RateLimitConfig {
+            responses_per_second: args.rate_limit,
+            nxdomain_per_second: args.nxdomain_rate_limit,
+            slip: args.rate_limit_slip,
+            ..Default::default()
+        },
+        zone_file,
+    };
+    // Sockets passed by systemd socket activation replace --bind
+    let activated_sockets = systemd::activated_sockets()?;
+    let (bind_addresses, binds) = if activated_sockets.is_empty() {
+        let binds = args.bind.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        (args.bind, binds)
+    } else {
+        (vec![], format!("{} sockets passed by systemd", activated_sockets.len()))
+    };
 
-    println!("✓ DNS server starting on {}", addr);
+    let mut server = DnsServer::new_with_binds(resolver, bind_addresses, handler_config)
+        .with_udp_sockets(args.udp_sockets)
+        .with_activated_sockets(activated_sockets)
+        .with_shutdown_timeout(Duration::from_secs(args.shutdown_timeout))
+        .with_metrics(metrics);
+    if let Some(interval) = systemd::watchdog_interval() {
+        server = server.with_watchdog(interval);
+    }
+    if let (Some(cert_path), Some(key_path)) = (args.tls_cert, args.tls_key) {
+        server = server.with_tls(TlsConfig {
+            cert_path,
+            key_path,
+            dot_addr: args.dot_bind,
+            doh_addr: args.doh_bind,
+            doh_hostname: args.doh_hostname,
+        });
+    }
+
+    println!("✓ DNS server starting on {}", binds);
     println!("\nServer is running. Press Ctrl+C to stop\n");
 
-    tokio::select! {
-        result = server.run() => result,
-        _ = signal::ctrl_c() => {
-            println!("\nShutdown signal received, stopping server...");
-            Ok(())
+    let shutdown = server.shutdown_handle();
+    let mut terminate = unix_signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = signal::ctrl_c() => {}
+        }
+        println!("\nShutdown signal received, stopping server...");
+        shutdown.shutdown();
+    });
+
+    server.run().await
+}
+
+/// Refreshes from Docker until the first refresh succeeds, so readiness doesn't wait for a query
+async fn initial_sync(resolver: Arc<DockerResolver>, retry_interval: Duration) {
+    let sync_status = resolver.sync_status();
+    while sync_status.borrow().successful_syncs == 0 {
+        // Failures are logged by the resolver, which also pauses refreshes while Docker is down
+        let _ = resolver.refresh().await;
+        if sync_status.borrow().successful_syncs == 0 {
+            tokio::time::sleep(retry_interval).await;
         }
     }
 }
+
+/// Keeps a file reloaded on changes. Without inotify the file is only read at startup.
+async fn watch_for_changes(path: PathBuf, watch: impl Future<Output = anyhow::Result<()>>) {
+    if let Err(e) = watch.await {
+        warn!("No longer watching {} for changes: {:#}", path.display(), e);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::circuit_breaker::CircuitState;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+
+/// Counters shared by the server components
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub queries: AtomicU64,
+    pub queries_denied: AtomicU64,
+    /// Responses dropped by response rate limiting
+    pub responses_dropped: AtomicU64,
+    /// Responses replaced by a truncated response by response rate limiting
+    pub responses_slipped: AtomicU64,
+    /// Docker refreshes that failed
+    pub docker_refresh_failures: AtomicU64,
+    /// State of the Docker circuit breaker, see [`Metrics::set_docker_circuit`]
+    docker_circuit: AtomicU8,
+}
+
+impl Metrics {
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_docker_circuit(&self, state: CircuitState) {
+        let value = match state {
+            CircuitState::Closed => 0,
+            CircuitState::Open => 1,
+            CircuitState::HalfOpen => 2,
+        };
+        self.docker_circuit.store(value, Ordering::Relaxed);
+    }
+
+    fn docker_circuit(&self) -> CircuitState {
+        match self.docker_circuit.load(Ordering::Relaxed) {
+            1 => CircuitState::Open,
+            2 => CircuitState::HalfOpen,
+            _ => CircuitState::Closed,
+        }
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            queries: self.queries.load(Ordering::Relaxed),
+            queries_denied: self.queries_denied.load(Ordering::Relaxed),
+            responses_dropped: self.responses_dropped.load(Ordering::Relaxed),
+            responses_slipped: self.responses_slipped.load(Ordering::Relaxed),
+            docker_refresh_failures: self.docker_refresh_failures.load(Ordering::Relaxed),
+            docker_circuit: self.docker_circuit(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MetricsSnapshot {
+    pub queries: u64,
+    pub queries_denied: u64,
+    pub responses_dropped: u64,
+    pub responses_slipped: u64,
+    pub docker_refresh_failures: u64,
+    pub docker_circuit: CircuitState,
+}
+
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "queries={} denied={} rate_limited={} slipped={} docker_failures={} docker_circuit={}",
+            self.queries,
+            self.queries_denied,
+            self.responses_dropped,
+            self.responses_slipped,
+            self.docker_refresh_failures,
+            self.docker_circuit
+        )
+    }
+}
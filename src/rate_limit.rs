@@ -0,0 +1,343 @@
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Hard cap on the tracked client prefixes. When reached, the least recently seen half is forgotten,
+/// so a flood from random (spoofed) prefixes costs amortized constant time per query.
+const MAX_CLIENT_BUCKETS: usize = 10_000;
+
+/// How often the full (idle) buckets are forgotten
+const IDLE_BUCKET_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A token bucket allowing `rate` events per second with bursts of up to `burst` events
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_update: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket
+    pub fn new(rate: f64, burst: f64, now: Instant) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last_update: now,
+        }
+    }
+
+    /// Takes a token if one is available
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_update).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last_update = now;
+    }
+
+    /// Whether the bucket would be full at `now`, i.e. forgetting it changes nothing
+    fn is_full_at(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_update).as_secs_f64();
+        self.tokens + elapsed * self.rate >= self.burst
+    }
+}
+
+/// Kind of response, each limited separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResponseKind {
+    Answer,
+    NxDomain,
+}
+
+/// What to do with a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitAction {
+    Send,
+    /// Send an empty truncated response, so a real client retries over TCP
+    Slip,
+    Drop,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// Responses per second per client prefix (0 = unlimited)
+    pub responses_per_second: u32,
+    /// NXDOMAIN responses per second per client prefix (0 = unlimited)
+    pub nxdomain_per_second: u32,
+    /// Every nth limited response is slipped instead of dropped (0 = always drop, 1 = always slip)
+    pub slip: u32,
+    /// Clients are grouped into prefixes of this length, so a spoofed flood from one network shares a limit
+    pub ipv4_prefix_len: u8,
+    pub ipv6_prefix_len: u8,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            responses_per_second: 0,
+            nxdomain_per_second: 0,
+            slip: 2,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 56,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.responses_per_second > 0 || self.nxdomain_per_second > 0
+    }
+
+    /// Whether some limited responses are sent truncated, for the client to retry over TCP
+    pub fn slips(&self) -> bool {
+        self.is_enabled() && self.slip > 0
+    }
+
+    fn rate(&self, kind: ResponseKind) -> u32 {
+        match kind {
+            ResponseKind::Answer => self.responses_per_second,
+            ResponseKind::NxDomain => self.nxdomain_per_second,
+        }
+    }
+}
+
+struct ClientState {
+    bucket: TokenBucket,
+    limited: u32,
+}
+
+struct Clients {
+    states: HashMap<(IpNet, ResponseKind), ClientState>,
+    last_sweep: Instant,
+}
+
+impl Clients {
+    /// Forgets the buckets that have refilled, at most once per sweep interval
+    fn forget_idle(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.last_sweep) < IDLE_BUCKET_SWEEP_INTERVAL {
+            return;
+        }
+        self.states.retain(|_, state| !state.bucket.is_full_at(now));
+        self.last_sweep = now;
+    }
+
+    /// Forgets the least recently seen half of the buckets
+    fn forget_least_recent(&mut self) {
+        let mut last_seen = self.states.values().map(|state| state.bucket.last_update).collect::<Vec<_>>();
+        let middle = last_seen.len() / 2;
+        let (_, cutoff, _) = last_seen.select_nth_unstable(middle);
+        let cutoff = *cutoff;
+        self.states.retain(|_, state| state.bucket.last_update > cutoff);
+    }
+}
+
+/// Response rate limiting (RRL) with a token bucket per client prefix and response kind
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    clients: Mutex<Clients>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            clients: Mutex::new(Clients {
+                states: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn check(&self, client: IpAddr, kind: ResponseKind) -> RateLimitAction {
+        self.check_at(client, kind, Instant::now())
+    }
+
+    fn check_at(&self, client: IpAddr, kind: ResponseKind, now: Instant) -> RateLimitAction {
+        let rate = self.config.rate(kind);
+        if rate == 0 {
+            return RateLimitAction::Send;
+        }
+
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.forget_idle(now);
+
+        let key = (self.client_prefix(client), kind);
+        if clients.states.len() >= MAX_CLIENT_BUCKETS && !clients.states.contains_key(&key) {
+            clients.forget_least_recent();
+        }
+        let state = clients.states.entry(key).or_insert_with(|| ClientState {
+            bucket: TokenBucket::new(f64::from(rate), f64::from(rate), now),
+            limited: 0,
+        });
+
+        if state.bucket.try_take(now) {
+            state.limited = 0;
+            return RateLimitAction::Send;
+        }
+
+        state.limited = state.limited.wrapping_add(1);
+        if self.config.slip > 0 && state.limited.is_multiple_of(self.config.slip) {
+            RateLimitAction::Slip
+        } else {
+            RateLimitAction::Drop
+        }
+    }
+
+    fn client_prefix(&self, client: IpAddr) -> IpNet {
+        let prefix_len = match client {
+            IpAddr::V4(_) => self.config.ipv4_prefix_len.min(32),
+            IpAddr::V6(_) => self.config.ipv6_prefix_len.min(128),
+        };
+        IpNet::new(client, prefix_len)
+            .map(|net| net.trunc())
+            .unwrap_or_else(|_| IpNet::from(client))
+    }
+}
+
+/// Caps how often something may happen globally, e.g. Docker refreshes triggered by cache misses
+pub struct GlobalLimit {
+    bucket: Mutex<TokenBucket>,
+}
+
+impl GlobalLimit {
+    /// Allows `count` events per `period`, all of which may happen at once
+    pub fn new(count: u32, period: Duration) -> Self {
+        let rate = f64::from(count) / period.as_secs_f64().max(f64::EPSILON);
+        Self {
+            bucket: Mutex::new(TokenBucket::new(rate, f64::from(count), Instant::now())),
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        bucket.try_take(Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(responses_per_second: u32, nxdomain_per_second: u32, slip: u32) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            responses_per_second,
+            nxdomain_per_second,
+            slip,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn token_bucket_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 2.0, start);
+
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn limits_clients_per_prefix() {
+        let limiter = limiter(2, 0, 0);
+        let now = Instant::now();
+        let client = IpAddr::from([192, 168, 1, 10]);
+        let neighbour = IpAddr::from([192, 168, 1, 20]);
+        let other = IpAddr::from([192, 168, 2, 10]);
+
+        assert_eq!(limiter.check_at(client, ResponseKind::Answer, now), RateLimitAction::Send);
+        assert_eq!(limiter.check_at(neighbour, ResponseKind::Answer, now), RateLimitAction::Send);
+        assert_eq!(limiter.check_at(client, ResponseKind::Answer, now), RateLimitAction::Drop);
+        assert_eq!(limiter.check_at(other, ResponseKind::Answer, now), RateLimitAction::Send);
+    }
+
+    #[test]
+    fn slips_every_nth_limited_response() {
+        let limiter = limiter(1, 0, 2);
+        let now = Instant::now();
+        let client = IpAddr::from([10, 0, 0, 1]);
+
+        let actions = (0..5)
+            .map(|_| limiter.check_at(client, ResponseKind::Answer, now))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            actions,
+            vec![
+                RateLimitAction::Send,
+                RateLimitAction::Drop,
+                RateLimitAction::Slip,
+                RateLimitAction::Drop,
+                RateLimitAction::Slip,
+            ]
+        );
+    }
+
+    #[test]
+    fn limits_nxdomain_separately() {
+        let limiter = limiter(0, 1, 0);
+        let now = Instant::now();
+        let client = IpAddr::from([10, 0, 0, 1]);
+
+        assert_eq!(limiter.check_at(client, ResponseKind::NxDomain, now), RateLimitAction::Send);
+        assert_eq!(limiter.check_at(client, ResponseKind::NxDomain, now), RateLimitAction::Drop);
+        assert_eq!(limiter.check_at(client, ResponseKind::Answer, now), RateLimitAction::Send);
+        assert_eq!(limiter.check_at(client, ResponseKind::Answer, now), RateLimitAction::Send);
+    }
+
+    #[test]
+    fn bounds_buckets_under_flood_from_random_prefixes() {
+        let limiter = limiter(1, 0, 0);
+        let start = Instant::now();
+        let victim = IpAddr::from([198, 51, 100, 1]);
+        assert_eq!(limiter.check_at(victim, ResponseKind::Answer, start), RateLimitAction::Send);
+
+        for i in 0..50_000u32 {
+            let now = start + Duration::from_micros(u64::from(i));
+            let [_, a, b, _] = i.to_be_bytes();
+            limiter.check_at(IpAddr::from([10, a, b, 1]), ResponseKind::Answer, now);
+            // A client seen during the flood keeps its limit
+            if i % 100 == 0 {
+                assert_eq!(limiter.check_at(victim, ResponseKind::Answer, now), RateLimitAction::Drop);
+            }
+            assert!(limiter.clients.lock().unwrap().states.len() <= MAX_CLIENT_BUCKETS);
+        }
+    }
+
+    #[test]
+    fn forgets_idle_buckets_after_sweep_interval() {
+        let limiter = limiter(1, 0, 0);
+        let start = Instant::now();
+        limiter.check_at(IpAddr::from([10, 0, 0, 1]), ResponseKind::Answer, start);
+        limiter.check_at(IpAddr::from([10, 0, 1, 1]), ResponseKind::Answer, start);
+
+        limiter.check_at(IpAddr::from([10, 0, 2, 1]), ResponseKind::Answer, start + IDLE_BUCKET_SWEEP_INTERVAL);
+
+        assert_eq!(limiter.clients.lock().unwrap().states.len(), 1);
+    }
+
+    #[test]
+    fn global_limit_caps_events() {
+        let limit = GlobalLimit::new(2, Duration::from_secs(60));
+
+        assert!(limit.try_acquire());
+        assert!(limit.try_acquire());
+        assert!(!limit.try_acquire());
+    }
+}
@@ -1,23 +1,321 @@
 use async_trait::async_trait;
-use std::collections::HashMap;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
-use crate::docker_client::{NetworkInfoProvider};
-use log::error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{Mutex, RwLock, watch};
+use crate::docker_client::{HealthStatus, NetworkAttachment, NetworkInfoProvider};
+use crate::metrics::Metrics;
+use crate::rate_limit::GlobalLimit;
+use crate::circuit_breaker::{BackoffConfig, CircuitBreaker, CircuitState, FailureOutcome};
+use crate::routes::{Reachability, RouteTable};
+use crate::snapshot::Snapshot;
+use log::{debug, error, info, warn};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct DnsResponse {
     pub ipv4_addresses: Vec<Ipv4Addr>,
     pub ipv6_addresses: Vec<Ipv6Addr>,
+    /// Canonical (lowercase) name of the container the addresses belong to, if known
+    pub canonical_name: Option<String>,
+    /// The networks the addresses are on
+    pub networks: Vec<NetworkAttachment>,
+    /// Weights for weighted answer ordering. Addresses not listed have the default weight.
+    pub weights: HashMap<IpAddr, u32>,
+    /// For single-address answers, the addresses of each container: only the first IPv4 and
+    /// IPv6 address of each is answered, once the answer is filtered for the client.
+    /// Empty if all addresses are answered.
+    pub single_address_groups: Vec<Vec<IpAddr>>,
+    /// From the snapshot of an earlier run and not confirmed by Docker yet,
+    /// so answered with a short TTL
+    pub stale: bool,
+    /// Preferences from split horizon, health, reachability and preferred networks.
+    /// Addresses not listed have the default preference.
+    pub preferences: HashMap<IpAddr, Preference>,
 }
 
+/// Where an address stands in the preference orderings, most significant first.
+/// Addresses are answered by preference; the answer order only reorders addresses of equal preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Preference {
+    /// Not on the querying client's network (split horizon "prefer")
+    pub remote: bool,
+    /// 0 healthy, 1 starting, 2 unhealthy (health policy "prefer-healthy")
+    pub health: u8,
+    /// The host has no route to it (reachability "deprioritize")
+    pub unrouted: bool,
+    /// Rank of its network among the container's networks (`--prefer-network` and the container's
+    /// preferred network), 0 for the most preferred one
+    pub network: u32,
+}
+
+/// Weight of addresses without an explicit weight
+pub const DEFAULT_WEIGHT: u32 = 100;
+
 impl DnsResponse {
     pub fn new(ipv4_addresses: Vec<Ipv4Addr>, ipv6_addresses: Vec<Ipv6Addr>) -> Self {
         Self {
             ipv4_addresses,
             ipv6_addresses,
+            canonical_name: None,
+            networks: vec![],
+            weights: HashMap::new(),
+            single_address_groups: vec![],
+            stale: false,
+            preferences: HashMap::new(),
+        }
+    }
+
+    pub fn with_canonical_name(mut self, canonical_name: impl Into<String>) -> Self {
+        self.canonical_name = Some(canonical_name.into().to_ascii_lowercase());
+        self
+    }
+
+    pub fn with_networks(mut self, networks: Vec<NetworkAttachment>) -> Self {
+        self.networks = networks;
+        self
+    }
+
+    /// Answers only the first IPv4 and IPv6 address left after filtering
+    pub fn with_single_address(mut self) -> Self {
+        let addresses = self.ipv4_addresses.iter().map(|ip| IpAddr::V4(*ip));
+        let addresses = addresses.chain(self.ipv6_addresses.iter().map(|ip| IpAddr::V6(*ip)));
+        self.single_address_groups = vec![addresses.collect()];
+        self
+    }
+
+    /// Sets the same weight for all addresses
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        let addresses = self.ipv4_addresses.iter().map(|ip| IpAddr::V4(*ip));
+        let addresses = addresses.chain(self.ipv6_addresses.iter().map(|ip| IpAddr::V6(*ip)));
+        self.weights = addresses.map(|ip| (ip, weight)).collect();
+        self
+    }
+
+    pub fn weight(&self, ip: IpAddr) -> u32 {
+        self.weights.get(&ip).copied().unwrap_or(DEFAULT_WEIGHT)
+    }
+
+    pub fn preference(&self, ip: IpAddr) -> Preference {
+        self.preferences.get(&ip).copied().unwrap_or_default()
+    }
+
+    /// Combines the responses of several containers sharing a name.
+    /// The canonical name is kept only if they all agree on it.
+    pub fn merge<'a>(responses: impl IntoIterator<Item = &'a DnsResponse>) -> Self {
+        let mut merged = Self::new(vec![], vec![]);
+        let mut canonical_names = HashSet::new();
+
+        for response in responses {
+            for ipv4 in &response.ipv4_addresses {
+                if !merged.ipv4_addresses.contains(ipv4) {
+                    merged.ipv4_addresses.push(*ipv4);
+                }
+            }
+            for ipv6 in &response.ipv6_addresses {
+                if !merged.ipv6_addresses.contains(ipv6) {
+                    merged.ipv6_addresses.push(*ipv6);
+                }
+            }
+            for network in &response.networks {
+                if !merged.networks.contains(network) {
+                    merged.networks.push(network.clone());
+                }
+            }
+            for (ip, weight) in &response.weights {
+                merged.weights.entry(*ip).or_insert(*weight);
+            }
+            for (ip, preference) in &response.preferences {
+                merged.preferences.entry(*ip).or_insert(*preference);
+            }
+            for group in &response.single_address_groups {
+                if !merged.single_address_groups.contains(group) {
+                    merged.single_address_groups.push(group.clone());
+                }
+            }
+            merged.stale |= response.stale;
+            canonical_names.insert(response.canonical_name.clone());
+        }
+
+        if canonical_names.len() == 1 {
+            merged.canonical_name = canonical_names.into_iter().next().flatten();
+        }
+
+        merged
+    }
+
+    /// Tailors the addresses to a client on one of the response's networks: the addresses
+    /// on the client's networks, of both families, are local to it.
+    /// Returns None if the answer should be unchanged, e.g. for clients on the host.
+    pub fn for_client(&self, client: IpAddr, split_horizon: SplitHorizon) -> Option<DnsResponse> {
+        if split_horizon == SplitHorizon::Off {
+            return None;
+        }
+
+        let local_addresses = self
+            .networks
+            .iter()
+            .filter(|network| network.contains(&client))
+            .flat_map(|network| &network.addresses)
+            .collect::<HashSet<_>>();
+        if local_addresses.is_empty() {
+            return None;
+        }
+
+        let is_local = |ip: IpAddr| local_addresses.contains(&ip);
+        let (local_ipv4, other_ipv4): (Vec<_>, Vec<_>) =
+            self.ipv4_addresses.iter().partition(|ip| is_local(IpAddr::V4(**ip)));
+        let (local_ipv6, other_ipv6): (Vec<_>, Vec<_>) =
+            self.ipv6_addresses.iter().partition(|ip| is_local(IpAddr::V6(**ip)));
+
+        let mut response = self.clone();
+        if split_horizon == SplitHorizon::Filter {
+            response.ipv4_addresses = local_ipv4.into_iter().copied().collect();
+            response.ipv6_addresses = local_ipv6.into_iter().copied().collect();
+        } else {
+            let other = other_ipv4.iter().map(|ip| IpAddr::V4(**ip));
+            for ip in other.chain(other_ipv6.iter().map(|ip| IpAddr::V6(**ip))) {
+                response.preferences.entry(ip).or_default().remote = true;
+            }
+            response.ipv4_addresses = local_ipv4.into_iter().chain(other_ipv4).copied().collect();
+            response.ipv6_addresses = local_ipv6.into_iter().chain(other_ipv6).copied().collect();
+        }
+        response.retain_answered_networks();
+
+        Some(response)
+    }
+
+    /// Picks the addresses of a single-address answer: of each container, the first IPv4 and
+    /// IPv6 address in answer order. Returns None if all addresses are answered.
+    pub fn single_addresses(&self) -> Option<DnsResponse> {
+        if self.single_address_groups.is_empty() {
+            return None;
+        }
+
+        let mut picked = HashSet::new();
+        for group in &self.single_address_groups {
+            let in_group = |ip: IpAddr| group.contains(&ip);
+            picked.extend(self.ipv4_addresses.iter().map(|ip| IpAddr::V4(*ip)).find(|ip| in_group(*ip)));
+            picked.extend(self.ipv6_addresses.iter().map(|ip| IpAddr::V6(*ip)).find(|ip| in_group(*ip)));
+        }
+
+        let mut response = self.clone();
+        response.ipv4_addresses.retain(|ip| picked.contains(&IpAddr::V4(*ip)));
+        response.ipv6_addresses.retain(|ip| picked.contains(&IpAddr::V6(*ip)));
+        response.single_address_groups.clear();
+        response.retain_answered_networks();
+        Some(response)
+    }
+
+    /// Keeps the networks consistent with the addresses after some were left out
+    fn retain_answered_networks(&mut self) {
+        let answered = self
+            .ipv4_addresses
+            .iter()
+            .map(|ip| IpAddr::V4(*ip))
+            .chain(self.ipv6_addresses.iter().map(|ip| IpAddr::V6(*ip)))
+            .collect::<HashSet<_>>();
+        for network in &mut self.networks {
+            network.addresses.retain(|ip| answered.contains(ip));
+        }
+        self.networks.retain(|network| !network.addresses.is_empty());
+    }
+}
+
+/// How answers are tailored to the network of the querying client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SplitHorizon {
+    /// Same answer for every client
+    #[default]
+    Off,
+    /// Addresses on the client's network come first
+    Prefer,
+    /// Only addresses on the client's network are returned
+    Filter,
+}
+
+impl FromStr for SplitHorizon {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "prefer" => Ok(Self::Prefer),
+            "filter" => Ok(Self::Filter),
+            _ => Err(format!("invalid split horizon mode '{}' (expected off, prefer or filter)", s)),
+        }
+    }
+}
+
+/// How container health affects which containers are returned for a name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HealthPolicy {
+    /// Return all containers
+    #[default]
+    Ignore,
+    /// Leave out unhealthy containers
+    ExcludeUnhealthy,
+    /// Return all containers, healthy ones first
+    PreferHealthy,
+    /// Only return healthy containers, holding back new ones until their first healthcheck passes
+    RequireHealthy,
+}
+
+impl HealthPolicy {
+    /// Selects containers by health, keeping their order otherwise.
+    /// If every container is left out, the unhealthy ones are returned instead;
+    /// starting containers stay held back under RequireHealthy.
+    fn select<T>(&self, containers: Vec<(HealthStatus, T)>) -> Vec<T> {
+        let is_healthy = |health: HealthStatus| matches!(health, HealthStatus::None | HealthStatus::Healthy);
+
+        let (selected, rest): (Vec<_>, Vec<_>) = match self {
+            Self::Ignore => return containers.into_iter().map(|(_, container)| container).collect(),
+            Self::PreferHealthy => {
+                let mut containers = containers;
+                containers.sort_by_key(|(health, _)| health_rank(*health));
+                return containers.into_iter().map(|(_, container)| container).collect();
+            }
+            Self::ExcludeUnhealthy => containers
+                .into_iter()
+                .partition(|(health, _)| *health != HealthStatus::Unhealthy),
+            Self::RequireHealthy => containers.into_iter().partition(|(health, _)| is_healthy(*health)),
+        };
+
+        if !selected.is_empty() {
+            return selected.into_iter().map(|(_, container)| container).collect();
+        }
+
+        rest.into_iter()
+            .filter(|(health, _)| *health == HealthStatus::Unhealthy)
+            .map(|(_, container)| container)
+            .collect()
+    }
+}
+
+/// Order of containers under "prefer-healthy"
+fn health_rank(health: HealthStatus) -> u8 {
+    match health {
+        HealthStatus::None | HealthStatus::Healthy => 0,
+        HealthStatus::Starting => 1,
+        HealthStatus::Unhealthy => 2,
+    }
+}
+
+impl FromStr for HealthPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ignore" => Ok(Self::Ignore),
+            "exclude-unhealthy" => Ok(Self::ExcludeUnhealthy),
+            "prefer-healthy" => Ok(Self::PreferHealthy),
+            "require-healthy" => Ok(Self::RequireHealthy),
+            _ => Err(format!(
+                "invalid health policy '{}' (expected ignore, exclude-unhealthy, prefer-healthy or require-healthy)",
+                s
+            )),
         }
     }
 }
@@ -25,10 +323,26 @@ impl DnsResponse {
 #[async_trait]
 pub trait DnsResolver: Send + Sync {
     async fn resolve(&self, domain: &str) -> Option<Arc<DnsResponse>>;
+
+    /// Resolves a domain for a specific client. Resolvers that can tailor the
+    /// answer to the client's network override this.
+    async fn resolve_for_client(&self, domain: &str, _client: IpAddr) -> Option<Arc<DnsResponse>> {
+        self.resolve(domain).await
+    }
+
+    /// Waits for background work, such as a refresh in progress, to finish
+    async fn wait_for_refresh(&self) {}
+
+    /// Whether a name that isn't found doesn't exist. Until then (e.g. before the first
+    /// Docker sync) unknown names are answered SERVFAIL instead of NXDOMAIN.
+    fn is_ready(&self) -> bool {
+        true
+    }
 }
 
+/// Fixed names, each with any number of IPv4 and IPv6 addresses
 pub struct StaticResolver {
-    mappings: HashMap<String, Ipv4Addr>,
+    mappings: HashMap<String, Arc<DnsResponse>>,
 }
 
 impl StaticResolver {
@@ -38,8 +352,55 @@ impl StaticResolver {
         }
     }
 
-    pub fn add_mapping(&mut self, domain: impl Into<String>, ip: Ipv4Addr) {
-        self.mappings.insert(domain.into(), ip);
+    /// Adds an address to a name
+    pub fn add_mapping(&mut self, domain: impl Into<String>, ip: impl Into<IpAddr>) {
+        let domain = domain.into().trim_end_matches('.').to_ascii_lowercase();
+        let response = self
+            .mappings
+            .entry(domain)
+            .or_insert_with(|| Arc::new(DnsResponse::new(vec![], vec![])));
+        let response = Arc::make_mut(response);
+        match ip.into() {
+            IpAddr::V4(ip) if !response.ipv4_addresses.contains(&ip) => response.ipv4_addresses.push(ip),
+            IpAddr::V6(ip) if !response.ipv6_addresses.contains(&ip) => response.ipv6_addresses.push(ip),
+            _ => {}
+        }
+    }
+
+    /// Parses /etc/hosts format: an address followed by one or more names per line,
+    /// with comments starting at '#'
+    pub fn from_hosts(content: &str) -> anyhow::Result<Self> {
+        let mut resolver = Self::new();
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(address) = fields.next() else {
+                continue;
+            };
+
+            let ip = address
+                .parse::<IpAddr>()
+                .map_err(|_| anyhow::anyhow!("line {}: invalid address '{}'", line_number, address))?;
+            let mut names = fields.peekable();
+            if names.peek().is_none() {
+                anyhow::bail!("line {}: no names for address {}", line_number, address);
+            }
+            for name in names {
+                resolver.add_mapping(name, ip);
+            }
+        }
+
+        Ok(resolver)
+    }
+
+    /// Number of names
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
     }
 }
 
@@ -52,17 +413,164 @@ impl Default for StaticResolver {
 #[async_trait]
 impl DnsResolver for StaticResolver {
     async fn resolve(&self, domain: &str) -> Option<Arc<DnsResponse>> {
-        self.mappings.get(domain).map(|&ip| {
-            Arc::new(DnsResponse::new(vec![ip], vec![]))
+        self.mappings.get(&domain.to_ascii_lowercase()).cloned()
+    }
+}
+
+/// A static name mapping, given as "<name>=<ip>"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostEntry {
+    pub name: String,
+    pub ip: IpAddr,
+}
+
+impl FromStr for HostEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid host entry '{}' (expected <name>=<ip address>)", s);
+        let (name, ip) = s.split_once('=').ok_or_else(invalid)?;
+        let name = name.trim().trim_end_matches('.');
+        if name.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            ip: ip.trim().parse().map_err(|_| invalid())?,
         })
     }
 }
 
+/// How a [`ChainResolver`] combines the answers of its resolvers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChainPolicy {
+    /// The first resolver that knows the name answers it
+    FirstMatch,
+    /// The addresses of all resolvers that know the name, in resolver order
+    Merge,
+    /// Each address family comes from the first resolver that has addresses of that family,
+    /// e.g. a static IPv4 address replaces the container's but its IPv6 addresses are kept
+    #[default]
+    Override,
+}
+
+impl FromStr for ChainPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "first-match" => Ok(Self::FirstMatch),
+            "merge" => Ok(Self::Merge),
+            "override" => Ok(Self::Override),
+            _ => Err(format!("invalid chain policy '{}' (expected first-match, merge or override)", s)),
+        }
+    }
+}
+
+/// Asks several resolvers, in order of precedence, and combines their answers
+pub struct ChainResolver {
+    resolvers: Vec<Arc<dyn DnsResolver>>,
+    policy: ChainPolicy,
+}
+
+impl ChainResolver {
+    pub fn new(resolvers: Vec<Arc<dyn DnsResolver>>, policy: ChainPolicy) -> Self {
+        Self { resolvers, policy }
+    }
+
+    /// Layers `overrides` on top of `base`
+    pub fn overlay(overrides: Arc<dyn DnsResolver>, base: Arc<dyn DnsResolver>) -> Self {
+        Self::new(vec![overrides, base], ChainPolicy::Override)
+    }
+
+    async fn resolve_chain(&self, domain: &str, client: Option<IpAddr>) -> Option<Arc<DnsResponse>> {
+        let mut answers = Vec::new();
+        for resolver in &self.resolvers {
+            let answer = match client {
+                Some(client) => resolver.resolve_for_client(domain, client).await,
+                None => resolver.resolve(domain).await,
+            };
+            if let Some(answer) = answer {
+                if self.policy == ChainPolicy::FirstMatch {
+                    return Some(answer);
+                }
+                answers.push(answer);
+            }
+        }
+
+        Self::combine(self.policy, answers)
+    }
+
+    fn combine(policy: ChainPolicy, answers: Vec<Arc<DnsResponse>>) -> Option<Arc<DnsResponse>> {
+        if answers.len() <= 1 || policy == ChainPolicy::FirstMatch {
+            return answers.into_iter().next();
+        }
+
+        if policy == ChainPolicy::Merge {
+            return Some(Arc::new(DnsResponse::merge(answers.iter().map(AsRef::as_ref))));
+        }
+
+        let ipv4 = answers.iter().find(|answer| !answer.ipv4_addresses.is_empty());
+        let ipv6 = answers.iter().find(|answer| !answer.ipv6_addresses.is_empty());
+        if ipv4.is_none() && ipv6.is_none() {
+            return answers.into_iter().next();
+        }
+
+        let mut combined = DnsResponse::merge(ipv4.into_iter().chain(ipv6).map(AsRef::as_ref));
+        combined.ipv4_addresses = ipv4.map(|answer| answer.ipv4_addresses.clone()).unwrap_or_default();
+        combined.ipv6_addresses = ipv6.map(|answer| answer.ipv6_addresses.clone()).unwrap_or_default();
+        Some(Arc::new(combined))
+    }
+}
+
+#[async_trait]
+impl DnsResolver for ChainResolver {
+    async fn resolve(&self, domain: &str) -> Option<Arc<DnsResponse>> {
+        self.resolve_chain(domain, None).await
+    }
+
+    async fn resolve_for_client(&self, domain: &str, client: IpAddr) -> Option<Arc<DnsResponse>> {
+        self.resolve_chain(domain, Some(client)).await
+    }
+
+    async fn wait_for_refresh(&self) {
+        for resolver in &self.resolvers {
+            resolver.wait_for_refresh().await;
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.resolvers.iter().all(|resolver| resolver.is_ready())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DockerResolverConfig {
     pub hit_timeout: Duration,
     pub miss_timeout: Duration,
     pub refresh_timeout: Duration,
+    /// Resolve subdomains of every container name to that container
+    /// (containers can also opt in individually with a label)
+    pub wildcard: bool,
+    /// Only answer the top-priority IPv4 and IPv6 address of each container,
+    /// chosen after the split-horizon and reachability filters
+    pub single_address: bool,
+    /// Tailor answers to the network of the querying client
+    pub split_horizon: SplitHorizon,
+    /// Drop or deprioritize addresses the host has no route to
+    pub reachability: Reachability,
+    /// How container health affects the answers
+    pub health_policy: HealthPolicy,
+    /// Cap on Docker refreshes triggered by cache misses per minute, across all clients (0 = unlimited)
+    pub max_miss_refreshes_per_minute: u32,
+    /// Save the names here whenever a refresh changes them, and serve the saved names
+    /// at startup until the first successful refresh
+    pub snapshot_path: Option<PathBuf>,
+    /// Saved names older than this are not served
+    pub snapshot_max_age: Duration,
+    /// When to pause refreshes while Docker keeps failing
+    pub backoff: BackoffConfig,
 }
 
 impl Default for DockerResolverConfig {
@@ -71,21 +579,106 @@ impl Default for DockerResolverConfig {
             hit_timeout: Duration::from_secs(60),
             miss_timeout: Duration::from_secs(5),
             refresh_timeout: Duration::from_secs(5),
+            wildcard: false,
+            single_address: false,
+            split_horizon: SplitHorizon::Off,
+            reachability: Reachability::Off,
+            health_policy: HealthPolicy::Ignore,
+            max_miss_refreshes_per_minute: 0,
+            snapshot_path: None,
+            snapshot_max_age: Duration::from_secs(24 * 60 * 60),
+            backoff: BackoffConfig::default(),
+        }
+    }
+}
+
+/// Outcome of the Docker refreshes so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncStatus {
+    pub successful_syncs: u64,
+    /// Containers and names seen by the last successful refresh
+    pub containers: usize,
+    pub names: usize,
+    /// Error of the last refresh, if it failed
+    pub last_error: Option<String>,
+    pub failed_syncs: u64,
+    /// Open while refreshes are paused after repeated failures
+    pub circuit: CircuitState,
+    /// The names come from a snapshot saved by an earlier run, as no refresh has succeeded yet
+    pub from_snapshot: bool,
+}
+
+#[derive(Clone, Default, PartialEq)]
+struct NetworkMappings {
+    names: HashMap<String, Arc<DnsResponse>>,
+    containers: usize,
+    /// Names of containers that have opted in to wildcard subdomains
+    wildcard_names: HashSet<String>,
+}
+
+impl NetworkMappings {
+    /// The names of a snapshot, marked stale
+    fn from_snapshot(snapshot: Snapshot) -> Self {
+        let names = snapshot
+            .names
+            .into_iter()
+            .map(|(name, response)| {
+                let mut response = Arc::unwrap_or_clone(response);
+                response.stale = true;
+                (name, Arc::new(response))
+            })
+            .collect();
+        Self {
+            names,
+            containers: snapshot.containers,
+            wildcard_names: snapshot.wildcard_names,
+        }
+    }
+
+    fn to_snapshot(&self) -> Snapshot {
+        Snapshot {
+            saved_at: SystemTime::now(),
+            containers: self.containers,
+            names: self.names.clone(),
+            wildcard_names: self.wildcard_names.clone(),
+        }
+    }
+
+    /// Looks up an exact name, then walks up label by label to the longest
+    /// matching wildcard name. Exact names always take precedence.
+    fn lookup(&self, domain: &str, wildcard_all: bool) -> Option<Arc<DnsResponse>> {
+        if let Some(response) = self.names.get(domain) {
+            return Some(Arc::clone(response));
+        }
+
+        let mut parent = domain;
+        while let Some((_, rest)) = parent.split_once('.') {
+            parent = rest;
+            if let Some(response) = self.names.get(parent)
+                && (wildcard_all || self.wildcard_names.contains(parent))
+            {
+                return Some(Arc::clone(response));
+            }
         }
+
+        None
     }
 }
 
 #[derive(Clone)]
 struct CachedNetworkData {
-    mappings: HashMap<String, Arc<DnsResponse>>,
+    mappings: NetworkMappings,
     last_refresh: Option<Instant>,
+    /// When the snapshot served until the first successful refresh was saved
+    snapshot_saved_at: Option<SystemTime>,
 }
 
 impl CachedNetworkData {
     fn new() -> Self {
         Self {
-            mappings: HashMap::new(),
+            mappings: NetworkMappings::default(),
             last_refresh: None,
+            snapshot_saved_at: None,
         }
     }
 
@@ -101,27 +694,87 @@ pub struct DockerResolver {
     provider: Arc<dyn NetworkInfoProvider>,
     config: DockerResolverConfig,
     cache: Arc<RwLock<CachedNetworkData>>,
+    miss_refresh_limit: Option<GlobalLimit>,
+    breaker: CircuitBreaker,
+    sync_status: watch::Sender<SyncStatus>,
+    metrics: Arc<Metrics>,
+    /// Held while saving a snapshot, so saves happen in refresh order
+    snapshot_save: Mutex<()>,
 }
 
 impl DockerResolver {
     pub fn new(provider: impl NetworkInfoProvider + 'static, config: DockerResolverConfig) -> Self {
+        let miss_refresh_limit = (config.max_miss_refreshes_per_minute > 0)
+            .then(|| GlobalLimit::new(config.max_miss_refreshes_per_minute, Duration::from_secs(60)));
+        let mut cache = CachedNetworkData::new();
+        let mut sync_status = SyncStatus::default();
+        let snapshot = config
+            .snapshot_path
+            .as_deref()
+            .and_then(|path| load_snapshot(path, config.snapshot_max_age));
+        if let Some(snapshot) = snapshot {
+            // Not counted as a refresh, so the first query still goes to Docker
+            cache.snapshot_saved_at = Some(snapshot.saved_at);
+            cache.mappings = NetworkMappings::from_snapshot(snapshot);
+            sync_status.containers = cache.mappings.containers;
+            sync_status.names = cache.mappings.names.len();
+            sync_status.from_snapshot = true;
+        }
+
         Self {
             provider: Arc::new(provider),
+            breaker: CircuitBreaker::new(config.backoff.clone()),
             config,
-            cache: Arc::new(RwLock::new(CachedNetworkData::new())),
+            cache: Arc::new(RwLock::new(cache)),
+            miss_refresh_limit,
+            sync_status: watch::Sender::new(sync_status),
+            metrics: Arc::new(Metrics::default()),
+            snapshot_save: Mutex::new(()),
         }
     }
 
+    /// Counts failed refreshes and tracks the circuit state in these metrics,
+    /// e.g. shared with the server
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        metrics.set_docker_circuit(self.breaker.state());
+        self.metrics = metrics;
+        self
+    }
+
+    /// Follows the outcome of the Docker refreshes
+    pub fn sync_status(&self) -> watch::Receiver<SyncStatus> {
+        self.sync_status.subscribe()
+    }
+
+    /// Refreshes from Docker now, unless a refresh happened within the miss timeout
+    /// or refreshes are paused after repeated failures
+    pub async fn refresh(&self) -> anyhow::Result<()> {
+        self.refresh_cache(false).await
+    }
+
+    /// Refreshes now, also while refreshes are paused, e.g. after the containers file changed
+    pub async fn force_refresh(&self) -> anyhow::Result<()> {
+        self.cache.write().await.last_refresh = None;
+        self.refresh_cache(true).await
+    }
+
     pub fn new_with_defaults(provider: impl NetworkInfoProvider + 'static) -> Self {
         Self::new(provider, DockerResolverConfig::default())
     }
 
-    async fn refresh_cache(&self) -> anyhow::Result<()> {
+    /// Failures are logged here, so callers needn't
+    async fn refresh_cache(&self, force: bool) -> anyhow::Result<()> {
         let mut cache = self.cache.write().await;
 
         if let Some(last_refresh) = cache.last_refresh && last_refresh.elapsed() < self.config.miss_timeout {
             return Ok(());
         }
+        if !force && !self.breaker.allow() {
+            return Ok(()); // Serve the cache until the next probe
+        }
+        if self.breaker.state() == CircuitState::HalfOpen {
+            debug!("Probing Docker (circuit half-open)");
+        }
 
         let mappings = tokio::time::timeout(
             self.config.refresh_timeout,
@@ -131,26 +784,195 @@ impl DockerResolver {
         .map_err(|_| anyhow::anyhow!("Docker API refresh timeout after {:?}", self.config.refresh_timeout));
 
         cache.last_refresh = Some(Instant::now());
-        cache.mappings = mappings??; // Throw error after last_refresh has been set
+        let mappings = mappings.and_then(|mappings| mappings);
+        let error = mappings.as_ref().err().map(|e| format!("{:#}", e));
+        match &error {
+            None => self.record_success(),
+            Some(e) => {
+                Metrics::increment(&self.metrics.docker_refresh_failures);
+                self.record_failure(e)
+            }
+        }
+        self.metrics.set_docker_circuit(self.breaker.state());
+        self.sync_status.send_modify(|status| {
+            match &mappings {
+                Ok(mappings) => {
+                    status.successful_syncs += 1;
+                    status.containers = mappings.containers;
+                    status.names = mappings.names.len();
+                    status.from_snapshot = false;
+                }
+                Err(_) => status.failed_syncs += 1,
+            }
+            status.last_error = error;
+            status.circuit = self.breaker.state();
+        });
+        let mappings = mappings?; // Throw error after last_refresh has been set
+
+        let changed = mappings != cache.mappings;
+        cache.mappings = mappings;
+        cache.snapshot_saved_at = None;
+        if changed && self.config.snapshot_path.is_some() {
+            // Save without blocking queries, but before a later refresh can save
+            let snapshot = cache.mappings.to_snapshot();
+            let _saving = self.snapshot_save.lock().await;
+            drop(cache);
+            self.save_snapshot(snapshot).await;
+        }
 
         Ok(())
     }
 
-    async fn fetch_and_build_mappings(&self) -> anyhow::Result<HashMap<String, Arc<DnsResponse>>>
+    fn record_success(&self) {
+        if let Some(failures) = self.breaker.record_success() {
+            info!("Docker is reachable again after {} failed refreshes (circuit closed)", failures);
+        }
+    }
+
+    /// Logs a failure once, and repeats of the same error only at debug level
+    fn record_failure(&self, error: &str) {
+        let repeated = self.sync_status.borrow().last_error.as_deref() == Some(error);
+        match self.breaker.record_failure() {
+            FailureOutcome::Opened(delay) => error!(
+                "Docker unavailable after {} failed refreshes, pausing refreshes for {:.1?} (circuit open): {}",
+                self.breaker.consecutive_failures(),
+                delay,
+                error
+            ),
+            FailureOutcome::Reopened(delay) if repeated => {
+                debug!("Docker still unavailable, next attempt in {:.1?}: {}", delay, error)
+            }
+            FailureOutcome::Reopened(delay) => warn!("Docker still unavailable, next attempt in {:.1?}: {}", delay, error),
+            FailureOutcome::Closed if repeated => debug!("Failed to refresh from Docker: {}", error),
+            FailureOutcome::Closed => warn!("Failed to refresh from Docker: {}", error),
+        }
+    }
+
+    /// Failing to save only costs a warm start, so errors are logged
+    async fn save_snapshot(&self, snapshot: Snapshot) {
+        let Some(path) = self.config.snapshot_path.clone() else {
+            return;
+        };
+
+        let names = snapshot.names.len();
+        match tokio::task::spawn_blocking(move || snapshot.save(&path)).await {
+            Ok(Ok(())) => debug!("Saved {} names to the snapshot", names),
+            Ok(Err(e)) => warn!("Failed to save the snapshot: {:#}", e),
+            Err(e) => warn!("Failed to save the snapshot: {}", e),
+        }
+    }
+
+    async fn fetch_and_build_mappings(&self) -> anyhow::Result<NetworkMappings>
     {
         let network_infos = self.provider.list_containers_network_info().await?;
+        let route_table = self.load_route_table().await;
 
-        let mut mappings = HashMap::new();
+        struct Container {
+            response: Arc<DnsResponse>,
+            health: HealthStatus,
+            wildcard: bool,
+        }
+
+        let mut containers = Vec::new();
+        let mut containers_by_name: HashMap<String, Vec<usize>> = HashMap::new();
         for info in network_infos {
-            let response = Arc::new(DnsResponse::new(info.ipv4_addresses, info.ipv6_addresses));
+            let mut preferences = HashMap::new();
+            let addresses = info.ipv4_addresses.iter().map(|ip| IpAddr::V4(*ip));
+            for ip in addresses.chain(info.ipv6_addresses.iter().map(|ip| IpAddr::V6(*ip))) {
+                let preference = Preference {
+                    remote: false,
+                    health: match self.config.health_policy {
+                        HealthPolicy::PreferHealthy => health_rank(info.health),
+                        _ => 0,
+                    },
+                    unrouted: self.config.reachability == Reachability::Deprioritize
+                        && route_table.as_ref().is_some_and(|route_table| !route_table.has_route(ip)),
+                    network: info.network_ranks.get(&ip).copied().unwrap_or_default(),
+                };
+                if preference != Preference::default() {
+                    preferences.insert(ip, preference);
+                }
+            }
+
+            let (ipv4_addresses, ipv6_addresses) = match &route_table {
+                Some(route_table) => (
+                    route_table.apply(info.ipv4_addresses, self.config.reachability),
+                    route_table.apply(info.ipv6_addresses, self.config.reachability),
+                ),
+                None => (info.ipv4_addresses, info.ipv6_addresses),
+            };
+
+            let mut response = DnsResponse::new(ipv4_addresses, ipv6_addresses)
+                .with_networks(info.networks);
+            response.preferences = preferences;
+            // Unreachable addresses may have been dropped
+            response.retain_answered_networks();
+            if self.config.single_address {
+                response = response.with_single_address();
+            }
+            if let Some(canonical_name) = info.names.first() {
+                response = response.with_canonical_name(canonical_name);
+            }
+            if let Some(weight) = info.weight {
+                response = response.with_weight(weight);
+            }
+
+            let index = containers.len();
+            containers.push(Container {
+                response: Arc::new(response),
+                health: info.health,
+                wildcard: info.wildcard,
+            });
+
             for name in info.names {
-                mappings.insert(name, Arc::clone(&response));
+                let indexes = containers_by_name.entry(name.to_ascii_lowercase()).or_default();
+                if indexes.last() != Some(&index) {
+                    indexes.push(index);
+                }
             }
         }
 
+        let mut mappings = NetworkMappings {
+            containers: containers.len(),
+            ..Default::default()
+        };
+        for (name, indexes) in containers_by_name {
+            let candidates = indexes.iter().map(|&index| (containers[index].health, index)).collect();
+            let selected = self.config.health_policy.select(candidates);
+
+            let response = match selected.as_slice() {
+                [] => continue, // Held back until healthy
+                [index] => Arc::clone(&containers[*index].response),
+                indexes => Arc::new(DnsResponse::merge(
+                    indexes.iter().map(|&index| containers[index].response.as_ref()),
+                )),
+            };
+
+            if selected.iter().any(|&index| containers[index].wildcard) {
+                mappings.wildcard_names.insert(name.clone());
+            }
+            mappings.names.insert(name, response);
+        }
+
         Ok(mappings)
     }
     
+    /// Reads the routing table if the reachability filter is enabled.
+    /// Addresses are left unfiltered if it can't be read.
+    async fn load_route_table(&self) -> Option<RouteTable> {
+        if self.config.reachability == Reachability::Off {
+            return None;
+        }
+
+        match RouteTable::load().await {
+            Ok(route_table) => Some(route_table),
+            Err(e) => {
+                warn!("Reachability filter disabled for this refresh: {:#}", e);
+                None
+            }
+        }
+    }
+
     async fn resolve_async(&self, domain: &str) -> Option<Arc<DnsResponse>> {
         // Read the cache first
         let (cached_result, hit_timeout_exceeded, miss_timeout_exceeded) = self.read_cache(domain).await;
@@ -161,41 +983,144 @@ impl DockerResolver {
 
             // Cache is older than hit timeout - refresh regardless of hit/miss
             (_, true, _) => {
-                self.get_refreshed_cache_entry(domain, "Failed to refresh DNS cache").await
+                self.get_refreshed_cache_entry(domain).await
             }
 
             // Cache miss, but within miss timeout - return None without refresh
             (None, false, false) => None,
 
+            // Cache miss, older than miss timeout, but too many misses have refreshed lately
+            (None, _, true) if !self.may_refresh_on_miss() => None,
+
             // Cache miss, and older than miss timeout - refresh and retry
             (None, _, true) => {
-                self.get_refreshed_cache_entry(domain, "Failed to refresh DNS cache on miss").await
+                self.get_refreshed_cache_entry(domain).await
             }
         }
     }
 
-    async fn get_refreshed_cache_entry(&self, domain: &str, err_context: &str) -> Option<Arc<DnsResponse>> {
-        if let Err(e) = self.refresh_cache().await {
-            error!("{}: {:#}", err_context, e);
+    /// Whether names from the snapshot are still served, though older than the maximum age
+    fn is_snapshot_expired(&self, cache: &CachedNetworkData) -> bool {
+        cache
+            .snapshot_saved_at
+            .is_some_and(|saved_at| saved_at.elapsed().unwrap_or_default() > self.config.snapshot_max_age)
+    }
+
+    /// Stops serving the snapshot names while Docker is still unreachable
+    async fn discard_expired_snapshot(&self) {
+        let mut cache = self.cache.write().await;
+        if !self.is_snapshot_expired(&cache) {
+            return; // Discarded or replaced meanwhile
+        }
+
+        warn!(
+            "Discarding the snapshot names: Docker hasn't answered within {}s of saving them",
+            self.config.snapshot_max_age.as_secs()
+        );
+        cache.mappings = NetworkMappings::default();
+        cache.snapshot_saved_at = None;
+        self.sync_status.send_modify(|status| {
+            status.containers = 0;
+            status.names = 0;
+            status.from_snapshot = false;
+        });
+    }
+
+    fn may_refresh_on_miss(&self) -> bool {
+        let allowed = self.miss_refresh_limit.as_ref().is_none_or(|limit| limit.try_acquire());
+        if !allowed {
+            debug!("Miss refresh limit reached, not refreshing");
         }
+        allowed
+    }
+
+    async fn get_refreshed_cache_entry(&self, domain: &str) -> Option<Arc<DnsResponse>> {
+        // Answer from the cache if the refresh fails; refresh_cache has logged it
+        let _ = self.refresh_cache(false).await;
 
         let cache = self.cache.read().await;
-        cache.mappings.get(domain).map(Arc::clone)
+        cache.mappings.lookup(domain, self.config.wildcard)
     }
     
     async fn read_cache(&self, domain: &str) -> (Option<Arc<DnsResponse>>, bool, bool) {
-        let cache = self.cache.read().await;
-        let result = cache.mappings.get(domain).map(Arc::clone);
+        let mut cache = self.cache.read().await;
+        if self.is_snapshot_expired(&cache) {
+            drop(cache);
+            self.discard_expired_snapshot().await;
+            cache = self.cache.read().await;
+        }
+        let result = cache.mappings.lookup(domain, self.config.wildcard);
         let hit_timeout_exceeded = cache.is_older_than(self.config.hit_timeout);
         let miss_timeout_exceeded = cache.is_older_than(self.config.miss_timeout);
         (result, hit_timeout_exceeded, miss_timeout_exceeded)
     }
 }
 
+/// Reads the snapshot of an earlier run. A missing file is expected on the first start;
+/// an unreadable or too old one is discarded and replaced after the next successful refresh.
+fn load_snapshot(path: &Path, max_age: Duration) -> Option<Snapshot> {
+    if !path.exists() {
+        return None;
+    }
+
+    match Snapshot::load(path) {
+        Ok(snapshot) if snapshot.age() > max_age => {
+            warn!(
+                "Discarding the snapshot saved {}s ago (maximum age {}s)",
+                snapshot.age().as_secs(),
+                max_age.as_secs()
+            );
+            None
+        }
+        Ok(snapshot) => {
+            info!(
+                "Serving {} names from the snapshot saved {}s ago until Docker is reachable",
+                snapshot.names.len(),
+                snapshot.age().as_secs()
+            );
+            Some(snapshot)
+        }
+        Err(e) => {
+            warn!("Discarding the snapshot: {:#}", e);
+            None
+        }
+    }
+}
+
 #[async_trait]
 impl DnsResolver for DockerResolver {
     async fn resolve(&self, domain: &str) -> Option<Arc<DnsResponse>> {
-        self.resolve_async(domain).await
+        let response = self.resolve_async(&domain.to_ascii_lowercase()).await?;
+        Some(with_single_addresses(response))
+    }
+
+    async fn wait_for_refresh(&self) {
+        // A refresh holds the write lock for its whole duration, then the save lock
+        drop(self.cache.read().await);
+        drop(self.snapshot_save.lock().await);
+    }
+
+    /// Ready after the first successful refresh, or with names from a snapshot
+    fn is_ready(&self) -> bool {
+        let status = self.sync_status.borrow();
+        status.successful_syncs > 0 || status.from_snapshot
+    }
+
+    async fn resolve_for_client(&self, domain: &str, client: IpAddr) -> Option<Arc<DnsResponse>> {
+        let response = self.resolve_async(&domain.to_ascii_lowercase()).await?;
+        let response = match response.for_client(client, self.config.split_horizon) {
+            Some(tailored) => Arc::new(tailored),
+            None => response,
+        };
+        Some(with_single_addresses(response))
+    }
+}
+
+/// Picks the single addresses once the answer is tailored to the client
+fn with_single_addresses(response: Arc<DnsResponse>) -> Arc<DnsResponse> {
+    match response.single_addresses() {
+        Some(single) => Arc::new(single),
+        None => response,
     }
 }
 
@@ -203,6 +1128,7 @@ impl DnsResolver for DockerResolver {
 mod tests {
     use super::*;
     use crate::docker_client::NetworkInfo;
+    use crate::test_util::TempDir;
     use async_trait::async_trait;
 
     #[tokio::test]
@@ -218,6 +1144,59 @@ mod tests {
         assert_eq!(response.ipv6_addresses, Vec::<Ipv6Addr>::new());
     }
 
+    #[tokio::test]
+    async fn resolves_configured_domain_case_insensitively() {
+        let mut resolver = StaticResolver::new();
+        resolver.add_mapping("My.Example.Local", Ipv4Addr::new(10, 11, 12, 13));
+
+        let result = resolver.resolve("MY.example.LOCAL").await;
+
+        assert!(result.is_some());
+        assert_eq!(result.unwrap().ipv4_addresses, vec![Ipv4Addr::new(10, 11, 12, 13)]);
+    }
+
+    #[tokio::test]
+    async fn resolves_several_addresses_per_name() {
+        let mut resolver = StaticResolver::new();
+        resolver.add_mapping("db", Ipv4Addr::new(10, 0, 0, 1));
+        resolver.add_mapping("db", Ipv4Addr::new(10, 0, 0, 2));
+        resolver.add_mapping("DB.", "fd00::1".parse::<Ipv6Addr>().unwrap());
+        resolver.add_mapping("db", Ipv4Addr::new(10, 0, 0, 1));
+
+        let response = resolver.resolve("db").await.unwrap();
+
+        assert_eq!(response.ipv4_addresses, vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]);
+        assert_eq!(response.ipv6_addresses, vec!["fd00::1".parse::<Ipv6Addr>().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn parses_hosts_file() {
+        let hosts = "\
+# Migration overrides
+192.168.1.10  db db-primary   # old host
+fd00::10      db
+
+10.0.0.5\tcache
+";
+        let resolver = StaticResolver::from_hosts(hosts).unwrap();
+
+        assert_eq!(resolver.len(), 3);
+        let db = resolver.resolve("db").await.unwrap();
+        assert_eq!(db.ipv4_addresses, vec![Ipv4Addr::new(192, 168, 1, 10)]);
+        assert_eq!(db.ipv6_addresses, vec!["fd00::10".parse::<Ipv6Addr>().unwrap()]);
+        assert!(resolver.resolve("db-primary").await.is_some());
+        assert!(resolver.resolve("cache").await.is_some());
+    }
+
+    #[test]
+    fn reports_hosts_file_errors_with_line_number() {
+        let error = StaticResolver::from_hosts("10.0.0.1 db\n\n10.0.0.300 cache\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 3: invalid address '10.0.0.300'");
+
+        let error = StaticResolver::from_hosts("10.0.0.1\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 1: no names for address 10.0.0.1");
+    }
+
     #[tokio::test]
     async fn returns_none_for_unknown_domain() {
         let resolver = StaticResolver::new();
@@ -227,6 +1206,180 @@ mod tests {
         assert_eq!(result, None);
     }
 
+    fn chain(policy: ChainPolicy) -> ChainResolver {
+        let mut overrides = StaticResolver::new();
+        overrides.add_mapping("db", Ipv4Addr::new(192, 168, 1, 10));
+        overrides.add_mapping("static-only", Ipv4Addr::new(192, 168, 1, 11));
+
+        let docker = DockerResolver::new_with_defaults(MockNetworkInfoProvider::new(vec![NetworkInfo {
+            names: vec!["db".to_string()],
+            ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
+            ipv6_addresses: vec!["fd00::2".parse().unwrap()],
+            ..Default::default()
+        }]));
+
+        ChainResolver::new(vec![Arc::new(overrides), Arc::new(docker)], policy)
+    }
+
+    #[test]
+    fn parses_host_entries() {
+        assert_eq!(
+            "db.docker.=192.168.1.10".parse::<HostEntry>(),
+            Ok(HostEntry {
+                name: "db.docker".to_string(),
+                ip: IpAddr::from([192, 168, 1, 10])
+            })
+        );
+        assert_eq!("db=fd00::1".parse::<HostEntry>().unwrap().ip, "fd00::1".parse::<IpAddr>().unwrap());
+        assert!("db".parse::<HostEntry>().is_err());
+        assert!("=192.168.1.10".parse::<HostEntry>().is_err());
+        assert!("db=localhost".parse::<HostEntry>().is_err());
+    }
+
+    #[tokio::test]
+    async fn chain_answers_from_first_match() {
+        let resolver = chain(ChainPolicy::FirstMatch);
+
+        let response = resolver.resolve("db").await.unwrap();
+
+        assert_eq!(response.ipv4_addresses, vec![Ipv4Addr::new(192, 168, 1, 10)]);
+        assert!(response.ipv6_addresses.is_empty());
+        assert!(resolver.resolve("static-only").await.is_some());
+        assert_eq!(resolver.resolve("unknown").await, None);
+    }
+
+    #[tokio::test]
+    async fn chain_merges_addresses_in_order() {
+        let resolver = chain(ChainPolicy::Merge);
+
+        let response = resolver.resolve("db").await.unwrap();
+
+        assert_eq!(
+            response.ipv4_addresses,
+            vec![Ipv4Addr::new(192, 168, 1, 10), Ipv4Addr::new(172, 17, 0, 2)]
+        );
+        assert_eq!(response.ipv6_addresses, vec!["fd00::2".parse::<Ipv6Addr>().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn chain_overrides_per_address_family() {
+        let resolver = chain(ChainPolicy::Override);
+
+        let response = resolver.resolve("db").await.unwrap();
+
+        assert_eq!(response.ipv4_addresses, vec![Ipv4Addr::new(192, 168, 1, 10)]);
+        assert_eq!(response.ipv6_addresses, vec!["fd00::2".parse::<Ipv6Addr>().unwrap()]);
+        assert_eq!(
+            resolver.resolve("static-only").await.unwrap().ipv4_addresses,
+            vec![Ipv4Addr::new(192, 168, 1, 11)]
+        );
+    }
+
+    fn network(subnets: &[&str], addresses: &[&str]) -> NetworkAttachment {
+        NetworkAttachment {
+            subnets: subnets.iter().map(|subnet| subnet.parse().unwrap()).collect(),
+            addresses: addresses.iter().map(|address| address.parse().unwrap()).collect(),
+        }
+    }
+
+    fn multi_network_response() -> DnsResponse {
+        DnsResponse::new(
+            vec![Ipv4Addr::new(172, 18, 0, 2), Ipv4Addr::new(172, 19, 0, 2)],
+            vec![],
+        )
+        .with_networks(vec![
+            network(&["172.18.0.0/16"], &["172.18.0.2"]),
+            network(&["172.19.0.0/16"], &["172.19.0.2"]),
+        ])
+    }
+
+    #[test]
+    fn split_horizon_prefers_addresses_on_client_network() {
+        let response = multi_network_response();
+        let client = IpAddr::from([172, 19, 0, 7]);
+
+        let tailored = response.for_client(client, SplitHorizon::Prefer).unwrap();
+
+        assert_eq!(
+            tailored.ipv4_addresses,
+            vec![Ipv4Addr::new(172, 19, 0, 2), Ipv4Addr::new(172, 18, 0, 2)]
+        );
+        // So that the answer order keeps the local address first
+        assert!(!tailored.preference(IpAddr::from([172, 19, 0, 2])).remote);
+        assert!(tailored.preference(IpAddr::from([172, 18, 0, 2])).remote);
+    }
+
+    #[test]
+    fn split_horizon_filters_addresses_on_client_network() {
+        let response = multi_network_response();
+        let client = IpAddr::from([172, 19, 0, 7]);
+
+        let tailored = response.for_client(client, SplitHorizon::Filter).unwrap();
+
+        assert_eq!(tailored.ipv4_addresses, vec![Ipv4Addr::new(172, 19, 0, 2)]);
+    }
+
+    #[test]
+    fn split_horizon_keeps_both_address_families_of_client_network() {
+        let response = DnsResponse::new(
+            vec![Ipv4Addr::new(172, 18, 0, 2), Ipv4Addr::new(172, 19, 0, 2)],
+            vec!["fd00:18::2".parse().unwrap(), "fd00:19::2".parse().unwrap()],
+        )
+        .with_networks(vec![
+            network(&["172.18.0.0/16", "fd00:18::/64"], &["172.18.0.2", "fd00:18::2"]),
+            network(&["172.19.0.0/16", "fd00:19::/64"], &["172.19.0.2", "fd00:19::2"]),
+        ]);
+
+        let tailored = response.for_client(IpAddr::from([172, 19, 0, 7]), SplitHorizon::Filter).unwrap();
+        assert_eq!(tailored.ipv4_addresses, vec![Ipv4Addr::new(172, 19, 0, 2)]);
+        assert_eq!(tailored.ipv6_addresses, vec!["fd00:19::2".parse::<Ipv6Addr>().unwrap()]);
+
+        let client = "fd00:18::7".parse().unwrap();
+        let tailored = response.for_client(client, SplitHorizon::Filter).unwrap();
+        assert_eq!(tailored.ipv4_addresses, vec![Ipv4Addr::new(172, 18, 0, 2)]);
+        assert_eq!(tailored.ipv6_addresses, vec!["fd00:18::2".parse::<Ipv6Addr>().unwrap()]);
+    }
+
+    #[test]
+    fn single_address_is_picked_per_container_after_filtering() {
+        let web1 = multi_network_response().with_single_address();
+        let web2 = DnsResponse::new(vec![Ipv4Addr::new(172, 18, 0, 3)], vec![])
+            .with_networks(vec![network(&["172.18.0.0/16"], &["172.18.0.3"])])
+            .with_single_address();
+        let merged = DnsResponse::merge([&web1, &web2]);
+
+        let single = merged.single_addresses().unwrap();
+        assert_eq!(single.ipv4_addresses, vec![Ipv4Addr::new(172, 18, 0, 2), Ipv4Addr::new(172, 18, 0, 3)]);
+        assert_eq!(
+            single.networks,
+            vec![network(&["172.18.0.0/16"], &["172.18.0.2"]), network(&["172.18.0.0/16"], &["172.18.0.3"])]
+        );
+
+        // A client on the second network of web-1 gets web-1's address there
+        let tailored = merged.for_client(IpAddr::from([172, 19, 0, 7]), SplitHorizon::Filter).unwrap();
+        let single = tailored.single_addresses().unwrap();
+        assert_eq!(single.ipv4_addresses, vec![Ipv4Addr::new(172, 19, 0, 2)]);
+        assert_eq!(single.networks, vec![network(&["172.19.0.0/16"], &["172.19.0.2"])]);
+
+        assert_eq!(multi_network_response().single_addresses(), None);
+    }
+
+    #[test]
+    fn split_horizon_keeps_all_addresses_for_host_clients() {
+        let response = multi_network_response();
+
+        assert_eq!(response.for_client(IpAddr::from([127, 0, 0, 1]), SplitHorizon::Filter), None);
+        assert_eq!(response.for_client(IpAddr::from([172, 19, 0, 7]), SplitHorizon::Off), None);
+    }
+
+    #[test]
+    fn parses_split_horizon_modes() {
+        assert_eq!("off".parse::<SplitHorizon>(), Ok(SplitHorizon::Off));
+        assert_eq!("Prefer".parse::<SplitHorizon>(), Ok(SplitHorizon::Prefer));
+        assert_eq!("filter".parse::<SplitHorizon>(), Ok(SplitHorizon::Filter));
+        assert!("sometimes".parse::<SplitHorizon>().is_err());
+    }
+
     // Tests for DockerResolver
     struct MockNetworkInfoProvider {
         data: Vec<NetworkInfo>,
@@ -259,6 +1412,7 @@ mod tests {
             names: vec!["container1".to_string()],
             ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
             ipv6_addresses: vec![],
+            ..Default::default()
         }]);
 
         let resolver = DockerResolver::new_with_defaults(provider);
@@ -271,6 +1425,24 @@ mod tests {
         assert_eq!(response.ipv6_addresses, Vec::<Ipv6Addr>::new());
     }
 
+    #[tokio::test]
+    async fn docker_resolver_resolves_container_name_case_insensitively() {
+        let provider = MockNetworkInfoProvider::new(vec![NetworkInfo {
+            names: vec!["MyApp".to_string()],
+            ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
+            ipv6_addresses: vec![],
+            ..Default::default()
+        }]);
+
+        let resolver = DockerResolver::new_with_defaults(provider);
+
+        let lower = resolver.resolve("myapp").await;
+        let upper = resolver.resolve("MYAPP").await;
+
+        assert!(lower.is_some());
+        assert_eq!(lower, upper);
+    }
+
     #[tokio::test]
     async fn docker_resolver_resolves_multiple_ips() {
         let ipv6 = "2001:db8::1".parse::<Ipv6Addr>().unwrap();
@@ -278,6 +1450,7 @@ mod tests {
             names: vec!["multi-ip-container".to_string()],
             ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2), Ipv4Addr::new(172, 17, 0, 3)],
             ipv6_addresses: vec![ipv6],
+            ..Default::default()
         }]);
 
         let resolver = DockerResolver::new_with_defaults(provider);
@@ -299,6 +1472,7 @@ mod tests {
             names: vec!["container1".to_string()],
             ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
             ipv6_addresses: vec![],
+            ..Default::default()
         }]);
 
         let call_count_tracker = provider.call_count.clone();
@@ -320,6 +1494,7 @@ mod tests {
             names: vec!["container1".to_string()],
             ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
             ipv6_addresses: vec![],
+            ..Default::default()
         }]);
 
         let call_count_tracker = provider.call_count.clone();
@@ -328,6 +1503,7 @@ mod tests {
             hit_timeout: Duration::from_millis(50),
             miss_timeout: Duration::from_millis(10),
             refresh_timeout: Duration::from_secs(5),
+            ..Default::default()
         };
 
         let resolver = DockerResolver::new(provider, config);
@@ -350,6 +1526,7 @@ mod tests {
             names: vec!["container1".to_string()],
             ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
             ipv6_addresses: vec![],
+            ..Default::default()
         }]);
 
         let call_count_tracker = provider.call_count.clone();
@@ -372,6 +1549,7 @@ mod tests {
             names: vec!["container1".to_string()],
             ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
             ipv6_addresses: vec![],
+            ..Default::default()
         }]);
 
         let call_count_tracker = provider.call_count.clone();
@@ -380,6 +1558,7 @@ mod tests {
             hit_timeout: Duration::from_secs(60),
             miss_timeout: Duration::from_millis(50),
             refresh_timeout: Duration::from_secs(5),
+            ..Default::default()
         };
 
         let resolver = DockerResolver::new(provider, config);
@@ -397,6 +1576,199 @@ mod tests {
         assert_eq!(*call_count_tracker.read().await, 2);
     }
 
+    #[tokio::test]
+    async fn docker_resolver_reports_sync_status() {
+        let provider = MockNetworkInfoProvider::new(vec![NetworkInfo {
+            names: vec!["container1".to_string(), "alias1".to_string()],
+            ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
+            ..Default::default()
+        }]);
+        let resolver = DockerResolver::new_with_defaults(provider);
+        let sync_status = resolver.sync_status();
+        assert_eq!(sync_status.borrow().successful_syncs, 0);
+
+        resolver.refresh().await.unwrap();
+
+        assert_eq!(
+            *sync_status.borrow(),
+            SyncStatus {
+                successful_syncs: 1,
+                containers: 1,
+                names: 2,
+                last_error: None,
+                failed_syncs: 0,
+                circuit: CircuitState::Closed,
+                from_snapshot: false,
+            }
+        );
+    }
+
+    struct UnreachableNetworkInfoProvider;
+
+    #[async_trait]
+    impl NetworkInfoProvider for UnreachableNetworkInfoProvider {
+        async fn list_containers_network_info(&self) -> Result<Vec<NetworkInfo>, anyhow::Error> {
+            anyhow::bail!("connection refused")
+        }
+    }
+
+    /// Fails while `down` is set, counting the calls
+    #[derive(Clone, Default)]
+    struct FlakyNetworkInfoProvider {
+        down: Arc<std::sync::atomic::AtomicBool>,
+        calls: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl NetworkInfoProvider for FlakyNetworkInfoProvider {
+        async fn list_containers_network_info(&self) -> Result<Vec<NetworkInfo>, anyhow::Error> {
+            use std::sync::atomic::Ordering;
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.down.load(Ordering::SeqCst) {
+                anyhow::bail!("connection refused");
+            }
+            Ok(vec![NetworkInfo {
+                names: vec!["container1".to_string()],
+                ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
+                ..Default::default()
+            }])
+        }
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_pauses_refreshes_while_docker_fails() {
+        use std::sync::atomic::Ordering;
+        let provider = FlakyNetworkInfoProvider::default();
+        provider.down.store(true, Ordering::SeqCst);
+        let config = DockerResolverConfig {
+            miss_timeout: Duration::ZERO,
+            backoff: BackoffConfig {
+                failure_threshold: 2,
+                initial_delay: Duration::from_millis(100),
+                max_delay: Duration::from_millis(100),
+            },
+            ..Default::default()
+        };
+        let metrics = Arc::new(Metrics::default());
+        let resolver = DockerResolver::new(provider.clone(), config).with_metrics(Arc::clone(&metrics));
+
+        for _ in 0..5 {
+            assert_eq!(resolver.resolve("container1").await, None);
+        }
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 2);
+        let status = resolver.sync_status().borrow().clone();
+        assert_eq!((status.failed_syncs, status.circuit), (2, CircuitState::Open));
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.docker_refresh_failures, snapshot.docker_circuit), (2, CircuitState::Open));
+
+        // The next query after the pause probes Docker again
+        provider.down.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        assert!(resolver.resolve("container1").await.is_some());
+        assert_eq!(resolver.sync_status().borrow().circuit, CircuitState::Closed);
+        assert_eq!(metrics.snapshot().docker_circuit, CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_serves_snapshot_until_docker_answers() {
+        let dir = TempDir::new("warm-start");
+        let config = DockerResolverConfig {
+            snapshot_path: Some(dir.join("snapshot.json")),
+            ..Default::default()
+        };
+
+        // First run: the refresh saves the names
+        let provider = MockNetworkInfoProvider::new(vec![NetworkInfo {
+            names: vec!["container1".to_string()],
+            ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
+            ..Default::default()
+        }]);
+        DockerResolver::new(provider, config.clone()).refresh().await.unwrap();
+
+        // After a restart, Docker isn't reachable yet
+        let resolver = DockerResolver::new(UnreachableNetworkInfoProvider, config.clone());
+        assert!(resolver.sync_status().borrow().from_snapshot);
+        let result = resolver.resolve("container1").await.unwrap();
+        assert_eq!(result.ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 2)]);
+        assert!(result.stale);
+        let status = resolver.sync_status().borrow().clone();
+        assert_eq!((status.successful_syncs, status.from_snapshot), (0, true));
+        assert!(status.last_error.is_some());
+
+        // Docker is back with other containers
+        let provider = MockNetworkInfoProvider::new(vec![NetworkInfo {
+            names: vec!["container2".to_string()],
+            ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 3)],
+            ..Default::default()
+        }]);
+        let resolver = DockerResolver::new(provider, config.clone());
+        assert!(!resolver.resolve("container2").await.unwrap().stale);
+        assert_eq!(resolver.resolve("container1").await, None);
+        assert!(!resolver.sync_status().borrow().from_snapshot);
+
+        // A corrupt snapshot is discarded
+        std::fs::write(dir.join("snapshot.json"), "{\"version\": 1, \"names\"").unwrap();
+        let resolver = DockerResolver::new(UnreachableNetworkInfoProvider, config);
+        assert!(!resolver.sync_status().borrow().from_snapshot);
+        assert_eq!(resolver.resolve("container2").await, None);
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_discards_old_snapshots() {
+        let dir = TempDir::new("old-snapshot");
+        let path = dir.join("snapshot.json");
+        let saved = |age: Duration| {
+            let response = DnsResponse::new(vec![Ipv4Addr::new(172, 17, 0, 2)], vec![]);
+            let snapshot = Snapshot {
+                saved_at: SystemTime::now() - age,
+                containers: 1,
+                names: HashMap::from([("container1".to_string(), Arc::new(response))]),
+                wildcard_names: HashSet::new(),
+            };
+            snapshot.save(&path).unwrap();
+        };
+        let config = DockerResolverConfig {
+            snapshot_path: Some(path.clone()),
+            snapshot_max_age: Duration::from_secs(60),
+            miss_timeout: Duration::ZERO,
+            ..Default::default()
+        };
+
+        saved(Duration::from_secs(120));
+        let resolver = DockerResolver::new(UnreachableNetworkInfoProvider, config.clone());
+        assert!(!resolver.sync_status().borrow().from_snapshot);
+        assert_eq!(resolver.resolve("container1").await, None);
+
+        // Served until it ages out while Docker is still down
+        saved(Duration::from_secs(59));
+        let resolver = DockerResolver::new(UnreachableNetworkInfoProvider, config);
+        assert!(resolver.resolve("container1").await.is_some());
+        resolver.cache.write().await.snapshot_saved_at = Some(SystemTime::now() - Duration::from_secs(61));
+        assert_eq!(resolver.resolve("container1").await, None);
+        assert!(!resolver.is_ready());
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_caps_refreshes_on_miss() {
+        let provider = MockNetworkInfoProvider::new(vec![]);
+        let call_count_tracker = provider.call_count.clone();
+
+        let config = DockerResolverConfig {
+            miss_timeout: Duration::from_millis(10),
+            max_miss_refreshes_per_minute: 2,
+            ..Default::default()
+        };
+        let resolver = DockerResolver::new(provider, config);
+
+        for name in ["random1", "random2", "random3", "random4"] {
+            assert_eq!(resolver.resolve(name).await, None);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // The first lookup refreshes because of the hit timeout, the next two use the miss limit
+        assert_eq!(*call_count_tracker.read().await, 3);
+    }
+
     #[tokio::test]
     async fn docker_resolver_handles_multiple_names_per_container() {
         let provider = MockNetworkInfoProvider::new(vec![NetworkInfo {
@@ -407,6 +1779,7 @@ mod tests {
             ],
             ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
             ipv6_addresses: vec![],
+            ..Default::default()
         }]);
 
         let resolver = DockerResolver::new_with_defaults(provider);
@@ -427,6 +1800,232 @@ mod tests {
         assert_eq!(response1.ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 2)]);
         assert_eq!(response2.ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 2)]);
         assert_eq!(response3.ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 2)]);
+
+        // The first name is the canonical name for all of them
+        assert_eq!(response1.canonical_name.as_deref(), Some("container1"));
+        assert_eq!(response2.canonical_name.as_deref(), Some("container1"));
+        assert_eq!(response3.canonical_name.as_deref(), Some("container1"));
+    }
+
+    fn wildcard_test_provider() -> MockNetworkInfoProvider {
+        MockNetworkInfoProvider::new(vec![
+            NetworkInfo {
+                names: vec!["web".to_string()],
+                ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
+                wildcard: true,
+                ..Default::default()
+            },
+            NetworkInfo {
+                names: vec!["api.web".to_string()],
+                ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 3)],
+                ..Default::default()
+            },
+            NetworkInfo {
+                names: vec!["db".to_string()],
+                ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 4)],
+                ..Default::default()
+            },
+        ])
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_resolves_subdomain_of_wildcard_container() {
+        let resolver = DockerResolver::new_with_defaults(wildcard_test_provider());
+
+        let result = resolver.resolve("anything.web").await;
+        assert_eq!(result.unwrap().ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 2)]);
+
+        let result = resolver.resolve("a.b.c.web").await;
+        assert_eq!(result.unwrap().ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 2)]);
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_prefers_explicit_name_over_wildcard() {
+        let resolver = DockerResolver::new_with_defaults(wildcard_test_provider());
+
+        let result = resolver.resolve("api.web").await;
+        assert_eq!(result.unwrap().ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 3)]);
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_ignores_subdomain_without_wildcard() {
+        let resolver = DockerResolver::new_with_defaults(wildcard_test_provider());
+
+        let result = resolver.resolve("anything.db").await;
+        assert_eq!(result, None);
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_resolves_subdomains_with_global_wildcard() {
+        let config = DockerResolverConfig {
+            wildcard: true,
+            ..Default::default()
+        };
+        let resolver = DockerResolver::new(wildcard_test_provider(), config);
+
+        let result = resolver.resolve("anything.db").await;
+        assert_eq!(result.unwrap().ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 4)]);
+
+        // Longest matching name wins
+        let result = resolver.resolve("v1.api.web").await;
+        assert_eq!(result.unwrap().ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 3)]);
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_tailors_answer_to_client_network() {
+        let provider = MockNetworkInfoProvider::new(vec![NetworkInfo {
+            names: vec!["api".to_string()],
+            ipv4_addresses: vec![Ipv4Addr::new(172, 18, 0, 2), Ipv4Addr::new(172, 19, 0, 2)],
+            networks: vec![
+                network(&["172.18.0.0/16"], &["172.18.0.2"]),
+                network(&["172.19.0.0/16"], &["172.19.0.2"]),
+            ],
+            ..Default::default()
+        }]);
+        let config = DockerResolverConfig {
+            split_horizon: SplitHorizon::Filter,
+            ..Default::default()
+        };
+        let resolver = DockerResolver::new(provider, config);
+
+        let backend = resolver.resolve_for_client("api", IpAddr::from([172, 19, 0, 7])).await;
+        assert_eq!(backend.unwrap().ipv4_addresses, vec![Ipv4Addr::new(172, 19, 0, 2)]);
+
+        let host = resolver.resolve_for_client("api", IpAddr::from([127, 0, 0, 1])).await;
+        assert_eq!(host.unwrap().ipv4_addresses.len(), 2);
+    }
+
+    #[test]
+    fn merges_responses_of_containers_sharing_a_name() {
+        let web1 = DnsResponse::new(vec![Ipv4Addr::new(172, 17, 0, 2)], vec![]).with_canonical_name("web-1");
+        let web2 = DnsResponse::new(vec![Ipv4Addr::new(172, 17, 0, 3)], vec![]).with_canonical_name("web-2");
+        let web2_again = web2.clone();
+
+        let merged = DnsResponse::merge([&web1, &web2]);
+        assert_eq!(
+            merged.ipv4_addresses,
+            vec![Ipv4Addr::new(172, 17, 0, 2), Ipv4Addr::new(172, 17, 0, 3)]
+        );
+        assert_eq!(merged.canonical_name, None);
+
+        let merged = DnsResponse::merge([&web2, &web2_again]);
+        assert_eq!(merged.ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 3)]);
+        assert_eq!(merged.canonical_name.as_deref(), Some("web-2"));
+    }
+
+    #[test]
+    fn health_policy_selects_containers() {
+        use HealthStatus::*;
+        let containers = || vec![(Unhealthy, 1), (None, 2), (Starting, 3), (Healthy, 4)];
+
+        assert_eq!(HealthPolicy::Ignore.select(containers()), vec![1, 2, 3, 4]);
+        assert_eq!(HealthPolicy::ExcludeUnhealthy.select(containers()), vec![2, 3, 4]);
+        assert_eq!(HealthPolicy::PreferHealthy.select(containers()), vec![2, 4, 3, 1]);
+        assert_eq!(HealthPolicy::RequireHealthy.select(containers()), vec![2, 4]);
+    }
+
+    #[test]
+    fn health_policy_falls_back_to_unhealthy_containers() {
+        use HealthStatus::*;
+
+        assert_eq!(HealthPolicy::ExcludeUnhealthy.select(vec![(Unhealthy, 1), (Unhealthy, 2)]), vec![1, 2]);
+        assert_eq!(HealthPolicy::RequireHealthy.select(vec![(Unhealthy, 1), (Starting, 2)]), vec![1]);
+        assert_eq!(HealthPolicy::RequireHealthy.select(vec![(Starting, 1)]), Vec::<i32>::new());
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_merges_containers_sharing_a_name() {
+        let provider = MockNetworkInfoProvider::new(vec![
+            NetworkInfo {
+                names: vec!["web-1".to_string(), "web".to_string()],
+                ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
+                health: HealthStatus::Healthy,
+                ..Default::default()
+            },
+            NetworkInfo {
+                names: vec!["web-2".to_string(), "web".to_string()],
+                ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 3)],
+                health: HealthStatus::Unhealthy,
+                ..Default::default()
+            },
+        ]);
+        let resolver = DockerResolver::new_with_defaults(provider);
+
+        let result = resolver.resolve("web").await;
+        assert_eq!(
+            result.unwrap().ipv4_addresses,
+            vec![Ipv4Addr::new(172, 17, 0, 2), Ipv4Addr::new(172, 17, 0, 3)]
+        );
+
+        let result = resolver.resolve("web-2").await;
+        assert_eq!(result.unwrap().ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 3)]);
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_ranks_containers_by_health() {
+        let provider = MockNetworkInfoProvider::new(vec![
+            NetworkInfo {
+                names: vec!["web".to_string()],
+                ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
+                health: HealthStatus::Unhealthy,
+                ..Default::default()
+            },
+            NetworkInfo {
+                names: vec!["web".to_string()],
+                ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 3)],
+                health: HealthStatus::Healthy,
+                ..Default::default()
+            },
+        ]);
+        let config = DockerResolverConfig {
+            health_policy: HealthPolicy::PreferHealthy,
+            ..Default::default()
+        };
+        let resolver = DockerResolver::new(provider, config);
+
+        let result = resolver.resolve("web").await.unwrap();
+        assert_eq!(result.ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 3), Ipv4Addr::new(172, 17, 0, 2)]);
+        assert_eq!(result.preference(IpAddr::from([172, 17, 0, 3])).health, 0);
+        assert_eq!(result.preference(IpAddr::from([172, 17, 0, 2])).health, 2);
+    }
+
+    #[tokio::test]
+    async fn docker_resolver_excludes_unhealthy_containers() {
+        let provider = MockNetworkInfoProvider::new(vec![
+            NetworkInfo {
+                names: vec!["web-1".to_string(), "web".to_string()],
+                ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
+                health: HealthStatus::Healthy,
+                ..Default::default()
+            },
+            NetworkInfo {
+                names: vec!["web-2".to_string(), "web".to_string()],
+                ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 3)],
+                health: HealthStatus::Unhealthy,
+                ..Default::default()
+            },
+            NetworkInfo {
+                names: vec!["new".to_string()],
+                ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 4)],
+                health: HealthStatus::Starting,
+                ..Default::default()
+            },
+        ]);
+        let config = DockerResolverConfig {
+            health_policy: HealthPolicy::RequireHealthy,
+            ..Default::default()
+        };
+        let resolver = DockerResolver::new(provider, config);
+
+        let result = resolver.resolve("web").await;
+        assert_eq!(result.unwrap().ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 2)]);
+
+        // Only unhealthy containers behind the name, so they are all returned
+        let result = resolver.resolve("web-2").await;
+        assert_eq!(result.unwrap().ipv4_addresses, vec![Ipv4Addr::new(172, 17, 0, 3)]);
+
+        // Held back until the first healthcheck passes
+        assert_eq!(resolver.resolve("new").await, None);
     }
 
     // Mock provider that simulates a slow Docker API
@@ -448,6 +2047,7 @@ mod tests {
                 names: vec!["slow-container".to_string()],
                 ipv4_addresses: vec![Ipv4Addr::new(172, 17, 0, 2)],
                 ipv6_addresses: vec![],
+                ..Default::default()
             }])
         }
     }
@@ -460,6 +2060,7 @@ mod tests {
             hit_timeout: Duration::from_secs(60),
             miss_timeout: Duration::from_secs(5),
             refresh_timeout: Duration::from_millis(50), // Short timeout
+            ..Default::default()
         };
 
         let resolver = DockerResolver::new(provider, config);
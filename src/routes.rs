@@ -0,0 +1,196 @@
+use anyhow::{Context, Result};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+const IPV4_ROUTES_PATH: &str = "/proc/net/route";
+const IPV6_ROUTES_PATH: &str = "/proc/net/ipv6_route";
+
+// Route flags from linux/route.h
+const RTF_UP: u32 = 0x0001;
+const RTF_REJECT: u32 = 0x0200;
+
+/// What to do with addresses the host has no route to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reachability {
+    /// Return all addresses
+    #[default]
+    Off,
+    /// Return addresses without a route last
+    Deprioritize,
+    /// Don't return addresses without a route
+    Drop,
+}
+
+impl FromStr for Reachability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "deprioritize" => Ok(Self::Deprioritize),
+            "drop" => Ok(Self::Drop),
+            _ => Err(format!("invalid reachability mode '{}' (expected off, deprioritize or drop)", s)),
+        }
+    }
+}
+
+/// The host's routes, read from the kernel routing table.
+///
+/// Default routes are ignored: an address only reachable through the default
+/// gateway is not on any network attached to the host (e.g. macvlan or remote overlay).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteTable {
+    routes: Vec<IpNet>,
+}
+
+impl RouteTable {
+    /// Reads the routing table from /proc. A missing IPv6 table (IPv6 disabled) is treated as empty.
+    pub async fn load() -> Result<Self> {
+        let ipv4_routes = tokio::fs::read_to_string(IPV4_ROUTES_PATH)
+            .await
+            .with_context(|| format!("Failed to read {}", IPV4_ROUTES_PATH))?;
+        let ipv6_routes = tokio::fs::read_to_string(IPV6_ROUTES_PATH).await.unwrap_or_default();
+
+        Ok(Self::parse(&ipv4_routes, &ipv6_routes))
+    }
+
+    /// Parses the contents of /proc/net/route and /proc/net/ipv6_route.
+    /// Lines that can't be parsed are skipped.
+    pub fn parse(ipv4_routes: &str, ipv6_routes: &str) -> Self {
+        let ipv4 = ipv4_routes.lines().skip(1).filter_map(parse_ipv4_route).map(IpNet::V4);
+        let ipv6 = ipv6_routes.lines().filter_map(parse_ipv6_route).map(IpNet::V6);
+
+        Self {
+            routes: ipv4.chain(ipv6).collect(),
+        }
+    }
+
+    pub fn has_route(&self, ip: IpAddr) -> bool {
+        self.routes.iter().any(|route| route.contains(&ip))
+    }
+
+    /// Applies the reachability mode to a list of addresses, keeping their relative order
+    pub fn apply<T: Copy + Into<IpAddr>>(&self, addresses: Vec<T>, reachability: Reachability) -> Vec<T> {
+        match reachability {
+            Reachability::Off => addresses,
+            Reachability::Drop => addresses
+                .into_iter()
+                .filter(|ip| self.has_route((*ip).into()))
+                .collect(),
+            Reachability::Deprioritize => {
+                let (routed, unrouted): (Vec<_>, Vec<_>) =
+                    addresses.into_iter().partition(|ip| self.has_route((*ip).into()));
+                routed.into_iter().chain(unrouted).collect()
+            }
+        }
+    }
+}
+
+/// Parses a line of /proc/net/route:
+/// `Iface Destination Gateway Flags RefCnt Use Metric Mask MTU Window IRTT`
+///
+/// Addresses are printed as the hex value of the network order bytes read as a native integer.
+fn parse_ipv4_route(line: &str) -> Option<Ipv4Net> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let destination = u32::from_str_radix(fields.get(1)?, 16).ok()?;
+    let flags = u32::from_str_radix(fields.get(3)?, 16).ok()?;
+    let mask = u32::from_str_radix(fields.get(7)?, 16).ok()?;
+
+    if !is_usable(flags) || mask == 0 {
+        return None;
+    }
+
+    let destination = Ipv4Addr::from(destination.to_ne_bytes());
+    let prefix_len = mask.count_ones() as u8;
+    Ipv4Net::new(destination, prefix_len).ok()
+}
+
+/// Parses a line of /proc/net/ipv6_route:
+/// `Destination PrefixLen Source SourcePrefixLen NextHop Metric RefCnt Use Flags Iface`
+fn parse_ipv6_route(line: &str) -> Option<Ipv6Net> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let destination = u128::from_str_radix(fields.first()?, 16).ok()?;
+    let prefix_len = u8::from_str_radix(fields.get(1)?, 16).ok()?;
+    let flags = u32::from_str_radix(fields.get(8)?, 16).ok()?;
+
+    if !is_usable(flags) || prefix_len == 0 {
+        return None;
+    }
+
+    Ipv6Net::new(Ipv6Addr::from(destination), prefix_len).ok()
+}
+
+fn is_usable(flags: u32) -> bool {
+    flags & RTF_UP != 0 && flags & RTF_REJECT == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IPV4_ROUTES: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t00000000\t0100A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t0000A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+docker0\t000011AC\t00000000\t0001\t0\t0\t0\t0000FFFF\t0\t0\t0
+br-dead\t000013AC\t00000000\t0000\t0\t0\t0\t0000FFFF\t0\t0\t0
+";
+
+    const IPV6_ROUTES: &str = "\
+fd000000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fd000000000000000000000000000001 00000400 00000001 00000000 00000003     eth0
+20010db8000000000000000000000000 30 00000000000000000000000000000000 00 00000000000000000000000000000000 00000400 00000001 00000000 00000201     eth0
+";
+
+    fn route_table() -> RouteTable {
+        RouteTable::parse(IPV4_ROUTES, IPV6_ROUTES)
+    }
+
+    #[test]
+    fn parses_routes_skipping_default_and_down_routes() {
+        assert_eq!(
+            route_table().routes,
+            vec![
+                "192.168.0.0/24".parse::<IpNet>().unwrap(),
+                "172.17.0.0/16".parse().unwrap(),
+                "fd00::/64".parse().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_route_for_address() {
+        let table = route_table();
+
+        assert!(table.has_route(IpAddr::from([172, 17, 0, 2])));
+        assert!(table.has_route("fd00::5".parse().unwrap()));
+        assert!(!table.has_route(IpAddr::from([172, 19, 0, 2]))); // Route is down
+        assert!(!table.has_route(IpAddr::from([10, 0, 0, 2]))); // Only the default route
+        assert!(!table.has_route("2001:db8::5".parse().unwrap())); // Reject route
+    }
+
+    #[test]
+    fn drops_or_deprioritizes_unrouted_addresses() {
+        let table = route_table();
+        let addresses = vec![Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(172, 17, 0, 2)];
+
+        assert_eq!(table.apply(addresses.clone(), Reachability::Off), addresses);
+        assert_eq!(
+            table.apply(addresses.clone(), Reachability::Drop),
+            vec![Ipv4Addr::new(172, 17, 0, 2)]
+        );
+        assert_eq!(
+            table.apply(addresses, Reachability::Deprioritize),
+            vec![Ipv4Addr::new(172, 17, 0, 2), Ipv4Addr::new(10, 0, 0, 2)]
+        );
+    }
+
+    #[test]
+    fn parses_reachability_modes() {
+        assert_eq!("off".parse::<Reachability>(), Ok(Reachability::Off));
+        assert_eq!("Deprioritize".parse::<Reachability>(), Ok(Reachability::Deprioritize));
+        assert_eq!("drop".parse::<Reachability>(), Ok(Reachability::Drop));
+        assert!("maybe".parse::<Reachability>().is_err());
+    }
+}
@@ -1,36 +1,778 @@
-use crate::custom_handler::CustomHandler;
+use crate::bind::{BindAddress, interface_addresses};
+use crate::custom_handler::{CustomHandler, CustomHandlerConfig};
+use crate::metrics::Metrics;
 use crate::resolver::DnsResolver;
-use anyhow::Result;
+use crate::systemd::{self, ActivatedSocket};
+use crate::tls::{CertificateStore, TlsConfig};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
 use hickory_server::ServerFuture;
+use hickory_server::authority::MessageResponseBuilder;
+use hickory_server::proto::op::ResponseCode;
+use hickory_server::proto::error::ProtoError;
+use hickory_server::server::{Request, RequestHandler, ResponseHandler, ResponseInfo};
+use log::{error, info, warn};
+use rustls::{Certificate, PrivateKey};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::{HashMap, HashSet};
+use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{Notify, oneshot, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{Instant, MissedTickBehavior};
+
+// Idle TCP connections are closed after this long
+const TCP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How often interface addresses are checked for changes
+const INTERFACE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// How long a graceful shutdown may take by default
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct DnsServer {
     resolver: Arc<dyn DnsResolver>,
-    bind_addr: SocketAddr,
-    suffix: String,
-    ttl: u32,
+    binds: Vec<BindAddress>,
+    handler_config: CustomHandlerConfig,
+    tls: Option<TlsConfig>,
+    udp_sockets: usize,
+    activated_sockets: Vec<ActivatedSocket>,
+    watchdog: Option<Duration>,
+    metrics: Option<Arc<Metrics>>,
+    shutdown_timeout: Duration,
+    shutdown: Arc<watch::Sender<bool>>,
+}
+
+/// Stops a running [`DnsServer`] gracefully
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<watch::Sender<bool>>);
+
+impl ShutdownHandle {
+    /// Stops accepting queries, lets the ones in progress finish (within the shutdown timeout)
+    /// and makes `run()` return. Shutting down a server that hasn't started yet stops it right after startup.
+    pub fn shutdown(&self) {
+        self.0.send_replace(true);
+    }
+}
+
+/// The handler with the queries in progress, shared by all listeners
+struct Serving {
+    handler: CustomHandler,
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    /// Queries finished so far, the heartbeat for the watchdog
+    completed: AtomicU64,
+    idle: Notify,
+}
+
+impl Serving {
+    /// Whether queries are being answered: none are waiting, or some finished since the last check
+    fn is_making_progress(&self, last_completed: &mut u64) -> bool {
+        let completed = self.completed.load(Ordering::SeqCst);
+        let progress = completed != *last_completed || self.in_flight.load(Ordering::SeqCst) == 0;
+        *last_completed = completed;
+        progress
+    }
+
+    /// Waits until no queries are in progress
+    async fn wait_idle(&self) {
+        loop {
+            let idle = self.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+            if self.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+/// Counts a query as in progress until dropped (also when the query's task is aborted)
+struct InFlight<'a>(&'a Serving);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.completed.fetch_add(1, Ordering::SeqCst);
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// Lets several server futures share one handler (and so its metrics and rate limits)
+#[derive(Clone)]
+struct SharedHandler(Arc<Serving>);
+
+#[async_trait]
+impl RequestHandler for SharedHandler {
+    async fn handle_request<R: ResponseHandler>(&self, request: &Request, response_handle: R) -> ResponseInfo {
+        let serving = &self.0;
+        serving.in_flight.fetch_add(1, Ordering::SeqCst);
+        let _in_flight = InFlight(serving);
+
+        // Shutting down: refuse new queries so that the client moves on to another server
+        // (or retries a restarted instance) right away instead of waiting for a timeout
+        if serving.draining.load(Ordering::SeqCst) {
+            return refuse(request, response_handle).await;
+        }
+
+        serving.handler.handle_request(request, response_handle).await
+    }
+}
+
+async fn refuse<R: ResponseHandler>(request: &Request, mut response_handle: R) -> ResponseInfo {
+    let response = MessageResponseBuilder::from_message_request(request).error_msg(request.header(), ResponseCode::Refused);
+    match response_handle.send_response(response).await {
+        Ok(info) => info,
+        Err(e) => {
+            warn!("Failed to refuse a query while shutting down: {:#}", e);
+            ResponseInfo::from(*request.header())
+        }
+    }
 }
 
 impl DnsServer {
-    pub fn new(resolver: Arc<dyn DnsResolver>, bind_addr: SocketAddr, suffix: String, ttl: u32) -> Self {
+    pub fn new(resolver: Arc<dyn DnsResolver>, bind_addr: SocketAddr, handler_config: CustomHandlerConfig) -> Self {
+        Self::new_with_binds(resolver, vec![BindAddress::Socket(bind_addr)], handler_config)
+    }
+
+    /// Listens on several addresses. Interface addresses are followed as they appear, change or disappear.
+    pub fn new_with_binds(
+        resolver: Arc<dyn DnsResolver>,
+        binds: Vec<BindAddress>,
+        handler_config: CustomHandlerConfig,
+    ) -> Self {
         Self {
             resolver,
-            bind_addr,
-            suffix,
-            ttl,
+            binds,
+            handler_config,
+            tls: None,
+            udp_sockets: 1,
+            activated_sockets: vec![],
+            watchdog: None,
+            metrics: None,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            shutdown: Arc::new(watch::Sender::new(false)),
         }
     }
 
+    /// Also serve DNS-over-TLS and/or DNS-over-HTTPS. The certificate is reloaded on SIGHUP.
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Open this many UDP sockets per address with SO_REUSEPORT, so the kernel spreads
+    /// queries over them and they are served in parallel on a multi-threaded runtime
+    pub fn with_udp_sockets(mut self, count: usize) -> Self {
+        self.udp_sockets = count.max(1);
+        self
+    }
+
+    /// Also serve on sockets passed in by systemd socket activation
+    pub fn with_activated_sockets(mut self, sockets: Vec<ActivatedSocket>) -> Self {
+        self.activated_sockets = sockets;
+        self
+    }
+
+    /// Ping the systemd watchdog at this interval while the listeners are running
+    pub fn with_watchdog(mut self, interval: Duration) -> Self {
+        self.watchdog = Some(interval);
+        self
+    }
+
+    /// Count into these metrics, e.g. shared with the resolver, instead of the server's own
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// How long a graceful shutdown may take before the remaining queries are abandoned
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Stops the server once running. Also available from the [`ServerHandle`].
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(Arc::clone(&self.shutdown))
+    }
+
+    /// Serves until a listener on a fixed address fails or a shutdown is requested
     pub async fn run(self) -> Result<()> {
-        let handler = CustomHandler::new(self.resolver, self.suffix, self.ttl);
-        let mut server = ServerFuture::new(handler);
+        self.start().wait().await
+    }
+
+    /// Starts serving in the background. Must be called from within a Tokio runtime.
+    pub fn start(self) -> ServerHandle {
+        let mut handler = CustomHandler::new(Arc::clone(&self.resolver), self.handler_config.clone());
+        if let Some(metrics) = &self.metrics {
+            handler = handler.with_metrics(Arc::clone(metrics));
+        }
+        let serving = Arc::new(Serving {
+            handler,
+            draining: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            idle: Notify::new(),
+        });
+        let shutdown = self.shutdown_handle();
+        let (ready_tx, ready) = watch::channel(None);
+        let (local_addrs_tx, local_addrs) = watch::channel(vec![]);
+
+        let task = tokio::spawn(self.serve(Arc::clone(&serving), ready_tx, local_addrs_tx));
+
+        ServerHandle {
+            serving,
+            shutdown,
+            ready,
+            local_addrs,
+            task,
+        }
+    }
+
+    async fn serve(
+        self,
+        serving: Arc<Serving>,
+        ready: watch::Sender<Option<Result<(), String>>>,
+        local_addrs: watch::Sender<Vec<SocketAddr>>,
+    ) -> Result<()> {
+        let handler = SharedHandler(Arc::clone(&serving));
+        // Only slipped rate limited responses are truncated; the others fit in UDP
+        let tcp = self.handler_config.rate_limit.slips();
+        let mut listeners = Listeners::new(handler.clone(), self.udp_sockets, tcp, local_addrs);
+        let mut shutdown = self.shutdown.subscribe();
+
+        // Fixed addresses must be available at startup, interface addresses may come later
+        let (interface_binds, socket_binds): (Vec<_>, Vec<_>) =
+            self.binds.into_iter().partition(BindAddress::is_interface);
+        let started: Result<_> = async {
+            for addr in socket_binds.iter().flat_map(|bind| bind.resolve(&[])) {
+                listeners.start(addr, false).await.with_context(|| format!("Failed to listen on {}", addr))?;
+            }
+            listeners.adopt(self.activated_sockets)?;
+            listeners.sync_interfaces(&interface_binds).await;
+
+            match self.tls {
+                Some(tls) => {
+                    // Fail at startup on a bad certificate; later reload errors keep the current one
+                    let certificate = tls.load_certificate()?;
+                    let tls_listeners = TlsListeners::start(&handler, &tls, certificate).await?;
+                    Ok(Some(tokio::spawn(serve_tls(handler, tls, tls_listeners, shutdown.clone()))))
+                }
+                None => Ok(None),
+            }
+        }
+        .await;
+        ready.send_replace(Some(started.as_ref().map(|_| ()).map_err(|e| format!("{:#}", e))));
+        let mut tls_task = started?;
+
+        // The interfaces were just synced
+        let mut poll = tokio::time::interval_at(Instant::now() + INTERFACE_POLL_INTERVAL, INTERFACE_POLL_INTERVAL);
+        poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // Pinged from this loop while queries are answered, so the pings stop if the handler hangs,
+        // this loop stalls or a listener fails
+        let mut watchdog = tokio::time::interval(self.watchdog.unwrap_or(INTERFACE_POLL_INTERVAL));
+        let mut last_completed = 0;
+        loop {
+            tokio::select! {
+                _ = poll.tick(), if !interface_binds.is_empty() => {
+                    listeners.sync_interfaces(&interface_binds).await;
+                }
+                _ = watchdog.tick(), if self.watchdog.is_some() => {
+                    if serving.is_making_progress(&mut last_completed) {
+                        systemd::notify_watchdog();
+                    } else {
+                        warn!(
+                            "No query finished since the last watchdog ping, {} in progress; not pinging",
+                            serving.in_flight.load(Ordering::SeqCst)
+                        );
+                    }
+                }
+                Some(stopped) = listeners.tasks.join_next() => {
+                    let (id, addr, result) = stopped?;
+                    listeners.stopped(id, addr, result)?;
+                }
+                Some(stopped) = async { Some(tls_task.as_mut()?.await) } => {
+                    // Only ends by itself when a listener fails, or on shutdown
+                    stopped??;
+                    tls_task = None;
+                }
+                _ = shutdown_requested(&mut shutdown) => break,
+                else => return Ok(()),
+            }
+        }
+
+        info!("Shutting down, finishing queries in progress");
+        systemd::notify_stopping();
+        let deadline = Instant::now() + self.shutdown_timeout;
+
+        serving.draining.store(true, Ordering::SeqCst);
+        if tokio::time::timeout_at(deadline, serving.wait_idle()).await.is_err() {
+            warn!(
+                "Shutdown timeout reached with {} queries in progress",
+                serving.in_flight.load(Ordering::SeqCst)
+            );
+        }
+        if tokio::time::timeout_at(deadline, serving.handler.resolver().wait_for_refresh()).await.is_err() {
+            warn!("Shutdown timeout reached during a Docker refresh");
+        }
+
+        listeners.stop_all(deadline).await;
+        if let Some(tls_task) = tls_task
+            && tokio::time::timeout_at(deadline, tls_task).await.is_err()
+        {
+            warn!("Shutdown timeout reached while stopping the TLS listeners");
+        }
+
+        info!("Stopped ({})", serving.handler.metrics().snapshot());
+        log::logger().flush();
+        Ok(())
+    }
+}
+
+/// Controls a server started with [`DnsServer::start`]. Dropping it leaves the server running.
+pub struct ServerHandle {
+    serving: Arc<Serving>,
+    shutdown: ShutdownHandle,
+    ready: watch::Receiver<Option<Result<(), String>>>,
+    local_addrs: watch::Receiver<Vec<SocketAddr>>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl ServerHandle {
+    /// Completes once the listeners are up, or fails if the server could not start
+    pub async fn ready(&self) -> Result<()> {
+        let mut ready = self.ready.clone();
+        let result = ready.wait_for(Option::is_some).await.context("Server stopped while starting")?;
+        match result.as_ref() {
+            Some(Err(e)) => Err(anyhow::anyhow!("{}", e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The addresses currently listened on for plain DNS, with the actual port when bound to port 0
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.local_addrs.borrow().clone()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Answers later queries from another resolver
+    pub fn set_resolver(&self, resolver: Arc<dyn DnsResolver>) {
+        self.serving.handler.set_resolver(resolver);
+    }
+
+    /// Answers later queries for another suffix (empty for all names)
+    pub fn set_suffix(&self, suffix: &str) {
+        self.serving.handler.set_suffix(suffix);
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        self.serving.handler.metrics()
+    }
+
+    /// Shuts the server down gracefully and waits until it has stopped
+    pub async fn shutdown(self) -> Result<()> {
+        self.shutdown.shutdown();
+        self.wait().await
+    }
+
+    /// Waits until the server stops, on a shutdown request or a fatal listener error
+    pub async fn wait(self) -> Result<()> {
+        self.task.await.context("Server task failed")?
+    }
+}
+
+struct Listener {
+    id: u64,
+    /// Differs from the requested address when bound to port 0
+    bound_addr: SocketAddr,
+    from_interface: bool,
+    /// Dropping this stops the listener
+    _stop: oneshot::Sender<()>,
+}
+
+/// The UDP and TCP listeners, with a server future per address so each can be stopped on its own
+struct Listeners {
+    handler: SharedHandler,
+    running: HashMap<SocketAddr, Listener>,
+    tasks: JoinSet<(u64, SocketAddr, Result<(), ProtoError>)>,
+    next_id: u64,
+    udp_sockets: usize,
+    /// Also listen on TCP, where clients retry truncated responses
+    tcp: bool,
+    local_addrs: watch::Sender<Vec<SocketAddr>>,
+}
+
+impl Listeners {
+    fn new(handler: SharedHandler, udp_sockets: usize, tcp: bool, local_addrs: watch::Sender<Vec<SocketAddr>>) -> Self {
+        Self {
+            handler,
+            udp_sockets,
+            tcp,
+            local_addrs,
+            running: HashMap::new(),
+            tasks: JoinSet::new(),
+            next_id: 0,
+        }
+    }
+
+    async fn start(&mut self, addr: SocketAddr, from_interface: bool) -> io::Result<()> {
+        let mut server = ServerFuture::new(self.handler.clone());
 
-        let socket = UdpSocket::bind(self.bind_addr).await?;
+        // With port 0 the other sockets must use the port the first one got
+        let socket = if self.udp_sockets > 1 {
+            bind_reuse_port(addr)?
+        } else {
+            UdpSocket::bind(addr).await?
+        };
+        let bound_addr = socket.local_addr()?;
         server.register_socket(socket);
+        for _ in 1..self.udp_sockets {
+            server.register_socket(bind_reuse_port(bound_addr)?);
+        }
+
+        if self.tcp {
+            let listener = TcpListener::bind(bound_addr).await?;
+            server.register_listener(listener, TCP_TIMEOUT);
+        }
+
+        self.spawn(addr, bound_addr, server, from_interface);
+        Ok(())
+    }
+
+    /// Serves the sockets passed in by systemd, with a server future per address
+    fn adopt(&mut self, sockets: Vec<ActivatedSocket>) -> io::Result<()> {
+        let mut servers = HashMap::new();
+        for socket in sockets {
+            let addr = socket.local_addr()?;
+            let server = servers
+                .entry(addr)
+                .or_insert_with(|| ServerFuture::new(self.handler.clone()));
+            match socket {
+                ActivatedSocket::Udp(socket) => server.register_socket_std(socket)?,
+                ActivatedSocket::Tcp(listener) => server.register_listener_std(listener, TCP_TIMEOUT)?,
+            }
+        }
+
+        for (addr, server) in servers {
+            self.spawn(addr, addr, server, false);
+        }
+        Ok(())
+    }
+
+    fn spawn(
+        &mut self,
+        addr: SocketAddr,
+        bound_addr: SocketAddr,
+        mut server: ServerFuture<SharedHandler>,
+        from_interface: bool,
+    ) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let (stop, stopped) = oneshot::channel::<()>();
+        self.tasks.spawn(async move {
+            let result = tokio::select! {
+                result = server.block_until_done() => result,
+                _ = stopped => server.shutdown_gracefully().await,
+            };
+            (id, addr, result)
+        });
+        self.running.insert(
+            addr,
+            Listener {
+                id,
+                bound_addr,
+                from_interface,
+                _stop: stop,
+            },
+        );
+        self.publish();
+    }
+
+    fn publish(&self) {
+        let mut addrs = self.running.values().map(|listener| listener.bound_addr).collect::<Vec<_>>();
+        addrs.sort();
+        self.local_addrs.send_replace(addrs);
+    }
+
+    /// Starts listening on new interface addresses and stops listening on the ones that are gone
+    async fn sync_interfaces(&mut self, binds: &[BindAddress]) {
+        if binds.is_empty() {
+            return;
+        }
+
+        let interfaces = match interface_addresses() {
+            Ok(interfaces) => interfaces,
+            Err(e) => {
+                warn!("Keeping the current listeners: {:#}", e);
+                self.warn_if_idle(binds);
+                return;
+            }
+        };
+        let wanted = binds.iter().flat_map(|bind| bind.resolve(&interfaces)).collect::<HashSet<_>>();
+
+        self.running.retain(|addr, listener| {
+            let keep = !listener.from_interface || wanted.contains(addr);
+            if !keep {
+                info!("Address {} is gone, no longer listening on it", addr);
+            }
+            keep
+        });
+        self.publish();
+
+        for addr in wanted {
+            if self.running.contains_key(&addr) {
+                continue;
+            }
+            match self.start(addr, true).await {
+                Ok(()) => info!("Listening on {}", addr),
+                // E.g. a new IPv6 address that is still tentative; retried on the next poll
+                Err(e) => warn!("Cannot listen on {} yet: {:#}", addr, e),
+            }
+        }
+        self.warn_if_idle(binds);
+    }
+
+    /// Warns on every poll while no listener is active, e.g. when no interface has an address yet
+    fn warn_if_idle(&self, binds: &[BindAddress]) {
+        if self.running.is_empty() {
+            let binds = binds.iter().map(ToString::to_string).collect::<Vec<_>>();
+            warn!("Not listening on any address, waiting for an address on {}", binds.join(", "));
+        }
+    }
+
+    /// Stops all listeners, abandoning the ones that haven't stopped by the deadline
+    async fn stop_all(&mut self, deadline: Instant) {
+        self.running.clear();
+        self.publish();
+        let stopped = async {
+            while let Some(stopped) = self.tasks.join_next().await {
+                if let Ok((_, addr, Err(e))) = stopped {
+                    warn!("Error while stopping the listener on {}: {:#}", addr, e);
+                }
+            }
+        };
+        if tokio::time::timeout_at(deadline, stopped).await.is_err() {
+            warn!("Shutdown timeout reached with {} listeners still stopping", self.tasks.len());
+            self.tasks.abort_all();
+        }
+    }
+
+    /// Handles a listener that has stopped. Only losing a fixed address is fatal.
+    fn stopped(&mut self, id: u64, addr: SocketAddr, result: Result<(), ProtoError>) -> Result<()> {
+        let Some(listener) = self.running.get(&addr).filter(|listener| listener.id == id) else {
+            return Ok(()); // Stopped on request
+        };
+
+        if !listener.from_interface {
+            result.with_context(|| format!("Stopped listening on {}", addr))?;
+            anyhow::bail!("Stopped listening on {}", addr);
+        }
+
+        match result {
+            Ok(()) => warn!("Stopped listening on {}", addr),
+            Err(e) => warn!("Stopped listening on {}: {:#}", addr, e),
+        }
+        self.running.remove(&addr);
+        self.publish();
+        Ok(())
+    }
+}
+
+fn bind_reuse_port(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// The DNS-over-TLS and DNS-over-HTTPS listeners
+struct TlsListeners {
+    certificates: Arc<CertificateStore>,
+    dot: Option<ServerFuture<SharedHandler>>,
+    /// Kept open while the DNS-over-HTTPS server is restarted
+    doh_socket: Option<std::net::TcpListener>,
+    doh: Option<ServerFuture<SharedHandler>>,
+}
+
+impl TlsListeners {
+    async fn start(
+        handler: &SharedHandler,
+        tls: &TlsConfig,
+        certificate: (Vec<Certificate>, PrivateKey),
+    ) -> Result<Self> {
+        let certificates = Arc::new(CertificateStore::new(&certificate)?);
+
+        let dot = match tls.dot_addr {
+            Some(addr) => {
+                let listener = TcpListener::bind(addr).await?;
+                let mut server = ServerFuture::new(handler.clone());
+                server.register_tls_listener_with_tls_config(listener, TCP_TIMEOUT, certificates.server_config())?;
+                Some(server)
+            }
+            None => None,
+        };
+
+        let doh_socket = match tls.doh_addr {
+            Some(addr) => Some(TcpListener::bind(addr).await?.into_std()?),
+            None => None,
+        };
+        let doh = match &doh_socket {
+            Some(socket) => Some(start_doh_server(handler, socket, tls, certificate)?),
+            None => None,
+        };
 
-        server.block_until_done().await?;
+        Ok(Self {
+            certificates,
+            dot,
+            doh_socket,
+            doh,
+        })
+    }
+
+    /// Serves a new certificate without closing the listening sockets
+    fn reload(&mut self, handler: &SharedHandler, tls: &TlsConfig, certificate: (Vec<Certificate>, PrivateKey)) -> Result<()> {
+        self.certificates.replace(&certificate)?;
+
+        // The DNS-over-HTTPS server takes its certificate when it starts: start a new one
+        // on the same socket, and let the old one finish its connections in the background
+        if let Some(socket) = &self.doh_socket {
+            let doh = start_doh_server(handler, socket, tls, certificate)?;
+            if let Some(mut old) = self.doh.replace(doh) {
+                tokio::spawn(async move {
+                    if let Err(e) = old.shutdown_gracefully().await {
+                        warn!("Error while stopping the previous DNS-over-HTTPS server: {:#}", e);
+                    }
+                });
+            }
+        }
         Ok(())
     }
+
+    /// Completes when a listener stops
+    async fn until_done(&mut self) -> Result<(), ProtoError> {
+        tokio::select! {
+            result = until_done(&mut self.dot) => result,
+            result = until_done(&mut self.doh) => result,
+        }
+    }
+
+    async fn shutdown(self) {
+        for mut server in self.dot.into_iter().chain(self.doh) {
+            if let Err(e) = server.shutdown_gracefully().await {
+                warn!("Error while stopping TLS listeners: {:#}", e);
+            }
+        }
+    }
+}
+
+async fn until_done(server: &mut Option<ServerFuture<SharedHandler>>) -> Result<(), ProtoError> {
+    match server {
+        Some(server) => server.block_until_done().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Serves DNS-over-HTTPS on a duplicate of the socket, so that stopping the server leaves it open
+fn start_doh_server(
+    handler: &SharedHandler,
+    socket: &std::net::TcpListener,
+    tls: &TlsConfig,
+    certificate: (Vec<Certificate>, PrivateKey),
+) -> Result<ServerFuture<SharedHandler>> {
+    let listener = TcpListener::from_std(socket.try_clone()?)?;
+    let mut server = ServerFuture::new(handler.clone());
+    server.register_https_listener(listener, TCP_TIMEOUT, certificate, tls.doh_hostname.clone())?;
+    Ok(server)
+}
+
+/// Completes once a shutdown is requested (or the server is gone)
+async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|&stop| stop).await;
+}
+
+/// Runs the TLS listeners, swapping in a freshly loaded certificate on SIGHUP.
+/// Fails if a listener stops before shutdown.
+async fn serve_tls(
+    handler: SharedHandler,
+    tls: TlsConfig,
+    mut listeners: TlsListeners,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            warn!("Certificate reload on SIGHUP not available: {:#}", e);
+            None
+        }
+    };
+
+    loop {
+        tokio::select! {
+            result = listeners.until_done() => {
+                result.context("TLS listeners stopped")?;
+                anyhow::bail!("TLS listeners stopped");
+            }
+            _ = shutdown_requested(&mut shutdown) => {
+                listeners.shutdown().await;
+                return Ok(());
+            }
+            Some(_) = async { hangup.as_mut()?.recv().await } => {}
+        }
+
+        info!("Reloading TLS certificate");
+        let reloaded = match tls.load_certificate() {
+            Ok(certificate) => listeners.reload(&handler, &tls, certificate),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = reloaded {
+            error!("Keeping the current TLS certificate: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    #[test]
+    fn watchdog_progress_needs_finished_queries_while_busy() {
+        let serving = Serving {
+            handler: CustomHandler::new(Arc::new(StaticResolver::new()), CustomHandlerConfig::default()),
+            draining: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            idle: Notify::new(),
+        };
+        let mut last_completed = 0;
+
+        // Idle
+        assert!(serving.is_making_progress(&mut last_completed));
+
+        // A query hangs
+        serving.in_flight.fetch_add(1, Ordering::SeqCst);
+        let hung = InFlight(&serving);
+        assert!(!serving.is_making_progress(&mut last_completed));
+
+        // Other queries still finish
+        serving.in_flight.fetch_add(1, Ordering::SeqCst);
+        drop(InFlight(&serving));
+        assert!(serving.is_making_progress(&mut last_completed));
+        assert!(!serving.is_making_progress(&mut last_completed));
+
+        drop(hung);
+        assert!(serving.is_making_progress(&mut last_completed));
+    }
 }
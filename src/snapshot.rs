@@ -0,0 +1,286 @@
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::docker_client::NetworkAttachment;
+use crate::resolver::{DnsResponse, Preference};
+
+/// Format version written to the file. Snapshots of other versions are discarded.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The names served after a successful refresh, saved so that a restart can answer
+/// before Docker is reachable again
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub saved_at: SystemTime,
+    pub containers: usize,
+    /// Responses by lowercase name
+    pub names: HashMap<String, Arc<DnsResponse>>,
+    /// Names of containers that have opted in to wildcard subdomains
+    pub wildcard_names: HashSet<String>,
+}
+
+impl Snapshot {
+    /// Reads and validates a snapshot
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid snapshot {}", path.display()))
+    }
+
+    /// Writes the snapshot to a temporary file and renames it over `path`,
+    /// so a crash never leaves a partial snapshot behind
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_vec(&self.to_file())?;
+        let temp_path = temp_path(path);
+        let write = || -> std::io::Result<()> {
+            let mut file = std::fs::File::create(&temp_path)?;
+            file.write_all(&content)?;
+            file.sync_all()?;
+            std::fs::rename(&temp_path, path)
+        };
+        write().with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// How long ago the snapshot was saved
+    pub fn age(&self) -> Duration {
+        self.saved_at.elapsed().unwrap_or_default()
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let version = serde_json::from_str::<FileVersion>(content)?.version;
+        if version != SNAPSHOT_VERSION {
+            anyhow::bail!("unsupported version {} (expected {})", version, SNAPSHOT_VERSION);
+        }
+
+        let file = serde_json::from_str::<SnapshotFile>(content)?;
+        let mut names = HashMap::new();
+        for (name, entry) in file.names {
+            if name.is_empty() || name != name.to_ascii_lowercase() || name.ends_with('.') {
+                anyhow::bail!("invalid name '{}'", name);
+            }
+            let response = entry.into_response().with_context(|| format!("name '{}'", name))?;
+            names.insert(name, Arc::new(response));
+        }
+        if let Some(name) = file.wildcard_names.iter().find(|name| !names.contains_key(*name)) {
+            anyhow::bail!("wildcard name '{}' has no addresses", name);
+        }
+
+        Ok(Self {
+            saved_at: UNIX_EPOCH + Duration::from_secs(file.saved_at),
+            containers: file.containers,
+            names,
+            wildcard_names: file.wildcard_names,
+        })
+    }
+
+    fn to_file(&self) -> SnapshotFile {
+        let names = self
+            .names
+            .iter()
+            .map(|(name, response)| (name.clone(), NameEntry::from_response(response)))
+            .collect();
+        SnapshotFile {
+            version: SNAPSHOT_VERSION,
+            saved_at: self.saved_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            containers: self.containers,
+            names,
+            wildcard_names: self.wildcard_names.clone(),
+        }
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+/// Read first, so that other versions are recognized whatever their layout
+#[derive(Deserialize)]
+struct FileVersion {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SnapshotFile {
+    version: u32,
+    /// Seconds since the Unix epoch
+    saved_at: u64,
+    containers: usize,
+    names: HashMap<String, NameEntry>,
+    #[serde(default)]
+    wildcard_names: HashSet<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NameEntry {
+    #[serde(default)]
+    ipv4: Vec<Ipv4Addr>,
+    #[serde(default)]
+    ipv6: Vec<Ipv6Addr>,
+    canonical_name: Option<String>,
+    #[serde(default)]
+    networks: Vec<NetworkEntry>,
+    #[serde(default)]
+    weights: HashMap<IpAddr, u32>,
+    #[serde(default)]
+    single_address_groups: Vec<Vec<IpAddr>>,
+    #[serde(default)]
+    preferences: HashMap<IpAddr, PreferenceEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NetworkEntry {
+    subnets: Vec<String>,
+    addresses: Vec<IpAddr>,
+}
+
+/// A preference without the split horizon, which depends on the client
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreferenceEntry {
+    #[serde(default)]
+    health: u8,
+    #[serde(default)]
+    unrouted: bool,
+    #[serde(default)]
+    network: u32,
+}
+
+impl NameEntry {
+    fn from_response(response: &DnsResponse) -> Self {
+        Self {
+            ipv4: response.ipv4_addresses.clone(),
+            ipv6: response.ipv6_addresses.clone(),
+            canonical_name: response.canonical_name.clone(),
+            networks: response
+                .networks
+                .iter()
+                .map(|network| NetworkEntry {
+                    subnets: network.subnets.iter().map(ToString::to_string).collect(),
+                    addresses: network.addresses.clone(),
+                })
+                .collect(),
+            weights: response.weights.clone(),
+            single_address_groups: response.single_address_groups.clone(),
+            preferences: response
+                .preferences
+                .iter()
+                .map(|(ip, preference)| {
+                    let entry = PreferenceEntry {
+                        health: preference.health,
+                        unrouted: preference.unrouted,
+                        network: preference.network,
+                    };
+                    (*ip, entry)
+                })
+                .collect(),
+        }
+    }
+
+    fn into_response(self) -> Result<DnsResponse> {
+        if self.ipv4.is_empty() && self.ipv6.is_empty() {
+            anyhow::bail!("no addresses");
+        }
+        let networks = self
+            .networks
+            .into_iter()
+            .map(|network| {
+                let subnets = network
+                    .subnets
+                    .iter()
+                    .map(|subnet| subnet.parse::<IpNet>().map_err(|_| anyhow::anyhow!("invalid subnet '{}'", subnet)))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(NetworkAttachment {
+                    subnets,
+                    addresses: network.addresses,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut response = DnsResponse::new(self.ipv4, self.ipv6).with_networks(networks);
+        if let Some(canonical_name) = self.canonical_name {
+            response = response.with_canonical_name(canonical_name);
+        }
+        response.weights = self.weights;
+        response.single_address_groups = self.single_address_groups;
+        response.preferences = self
+            .preferences
+            .into_iter()
+            .map(|(ip, entry)| {
+                let preference = Preference {
+                    remote: false,
+                    health: entry.health,
+                    unrouted: entry.unrouted,
+                    network: entry.network,
+                };
+                (ip, preference)
+            })
+            .collect();
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn saves_and_loads_names() {
+        let mut web = DnsResponse::new(vec![Ipv4Addr::new(172, 18, 0, 2)], vec!["fd00::2".parse().unwrap()])
+            .with_canonical_name("project-web-1")
+            .with_networks(vec![NetworkAttachment {
+                subnets: vec!["172.18.0.0/16".parse().unwrap(), "fd00::/64".parse().unwrap()],
+                addresses: vec![IpAddr::from([172, 18, 0, 2]), "fd00::2".parse().unwrap()],
+            }])
+            .with_weight(50)
+            .with_single_address();
+        web.preferences = HashMap::from([(
+            "fd00::2".parse().unwrap(),
+            Preference {
+                unrouted: true,
+                ..Default::default()
+            },
+        )]);
+        let snapshot = Snapshot {
+            saved_at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            containers: 1,
+            names: HashMap::from([("web".to_string(), Arc::new(web.clone())), ("project-web-1".to_string(), Arc::new(web))]),
+            wildcard_names: HashSet::from(["web".to_string()]),
+        };
+        let dir = TempDir::new("snapshot-save");
+        let path = dir.join("snapshot.json");
+
+        snapshot.save(&path).unwrap();
+
+        assert_eq!(Snapshot::load(&path).unwrap(), snapshot);
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn rejects_other_versions_and_corrupt_snapshots() {
+        let dir = TempDir::new("snapshot-invalid");
+        let path = dir.join("snapshot.json");
+        let load_error = |content: &str| {
+            std::fs::write(&path, content).unwrap();
+            format!("{:#}", Snapshot::load(&path).unwrap_err())
+        };
+
+        assert!(load_error(r#"{"version": 2, "layout": "new"}"#).contains("unsupported version 2 (expected 1)"));
+        assert!(load_error(r#"{"version": 1, "saved_at": 0, "containers": 1, "names": {"web": {"ipv4": ["172.18"#)
+            .contains("EOF"));
+        assert!(load_error(r#"{"version": 1, "saved_at": 0, "containers": 1, "names": {"web": {}}}"#)
+            .contains("name 'web': no addresses"));
+        assert!(load_error(r#"{"version": 1, "saved_at": 0, "containers": 1, "names": {"Web": {"ipv4": ["10.0.0.1"]}}}"#)
+            .contains("invalid name 'Web'"));
+    }
+}
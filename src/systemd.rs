@@ -0,0 +1,157 @@
+use crate::circuit_breaker::CircuitState;
+use crate::resolver::SyncStatus;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use sd_notify::NotifyState;
+use socket2::{Socket, Type};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::os::fd::FromRawFd;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// A socket passed in by systemd socket activation
+#[derive(Debug)]
+pub enum ActivatedSocket {
+    Udp(UdpSocket),
+    Tcp(TcpListener),
+}
+
+impl ActivatedSocket {
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        match self {
+            Self::Udp(socket) => socket.local_addr(),
+            Self::Tcp(listener) => listener.local_addr(),
+        }
+    }
+}
+
+/// Takes the sockets passed in with `LISTEN_FDS`. Returns none if not socket activated.
+pub fn activated_sockets() -> Result<Vec<ActivatedSocket>> {
+    let fds = sd_notify::listen_fds().context("Failed to read sockets passed by systemd")?;
+
+    fds.map(|fd| {
+        // SAFETY: systemd passes these descriptors to this process only, and listen_fds()
+        // unsets LISTEN_FDS so they are taken exactly once
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        socket.set_nonblocking(true)?;
+        let socket_type = socket.r#type()?;
+        if socket_type == Type::DGRAM {
+            Ok(ActivatedSocket::Udp(socket.into()))
+        } else if socket_type == Type::STREAM {
+            Ok(ActivatedSocket::Tcp(socket.into()))
+        } else {
+            anyhow::bail!("Socket {} passed by systemd is neither UDP nor TCP", fd)
+        }
+    })
+    .collect()
+}
+
+/// How often to ping the watchdog, if systemd enabled it: half its timeout, as recommended
+pub fn watchdog_interval() -> Option<Duration> {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec)
+        .then(|| (Duration::from_micros(usec) / 2).max(Duration::from_millis(1)))
+}
+
+pub fn notify_watchdog() {
+    notify(&[NotifyState::Watchdog]);
+}
+
+pub fn notify_stopping() {
+    notify(&[NotifyState::Stopping]);
+}
+
+/// Reports Docker syncs to systemd: READY=1 after the first successful one (or right away when serving
+/// a snapshot), and STATUS= after each
+pub async fn notify_sync_status(mut sync_status: watch::Receiver<SyncStatus>) {
+    let mut ready = false;
+    loop {
+        let status = sync_status.borrow_and_update().clone();
+        let text = status_text(&status);
+        if !ready && (status.successful_syncs > 0 || status.from_snapshot) {
+            ready = true;
+            if !status.from_snapshot {
+                info!("First Docker sync done: {}", text);
+            }
+            notify(&[NotifyState::Ready, NotifyState::Status(&text)]);
+        } else {
+            notify(&[NotifyState::Status(&text)]);
+        }
+
+        if sync_status.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+fn status_text(status: &SyncStatus) -> String {
+    let text = sync_text(status);
+    match status.circuit {
+        CircuitState::Closed => text,
+        circuit => format!("{} [Docker circuit {}]", text, circuit),
+    }
+}
+
+fn sync_text(status: &SyncStatus) -> String {
+    if status.from_snapshot {
+        let waiting = match &status.last_error {
+            Some(e) => format!("waiting for Docker: {}", e),
+            None => "waiting for Docker".to_string(),
+        };
+        return format!("Serving {} names from the snapshot, {}", status.names, waiting);
+    }
+
+    match (&status.last_error, status.successful_syncs) {
+        (Some(e), 0) => format!("Waiting for Docker: {}", e),
+        (None, 0) => "Waiting for Docker".to_string(),
+        (Some(e), _) => format!(
+            "Serving {} names from {} containers (last refresh failed: {})",
+            status.names, status.containers, e
+        ),
+        (None, _) => format!("Serving {} names from {} containers", status.names, status.containers),
+    }
+}
+
+/// Does nothing when not running under systemd
+fn notify(state: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        warn!("Failed to notify systemd: {:#}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_sync_status() {
+        let mut status = SyncStatus::default();
+        assert_eq!(status_text(&status), "Waiting for Docker");
+
+        status.last_error = Some("connection refused".to_string());
+        assert_eq!(status_text(&status), "Waiting for Docker: connection refused");
+
+        status = SyncStatus {
+            successful_syncs: 3,
+            containers: 4,
+            names: 9,
+            last_error: None,
+            ..Default::default()
+        };
+        assert_eq!(status_text(&status), "Serving 9 names from 4 containers");
+
+        status = SyncStatus {
+            names: 9,
+            last_error: Some("connection refused".to_string()),
+            from_snapshot: true,
+            ..Default::default()
+        };
+        assert_eq!(status_text(&status), "Serving 9 names from the snapshot, waiting for Docker: connection refused");
+
+        status.circuit = CircuitState::Open;
+        assert_eq!(
+            status_text(&status),
+            "Serving 9 names from the snapshot, waiting for Docker: connection refused [Docker circuit open]"
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use hickory_server::proto::rustls::tls_server::{read_cert, read_key};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{CertifiedKey, any_supported_type};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// Certificate and listen addresses for encrypted DNS
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain
+    pub cert_path: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1)
+    pub key_path: PathBuf,
+    /// DNS-over-TLS listen address (usually port 853)
+    pub dot_addr: Option<SocketAddr>,
+    /// DNS-over-HTTPS listen address, answering on /dns-query
+    pub doh_addr: Option<SocketAddr>,
+    /// Only answer DNS-over-HTTPS requests for this host name
+    pub doh_hostname: Option<String>,
+}
+
+impl TlsConfig {
+    /// Reads the certificate chain and private key from disk
+    pub fn load_certificate(&self) -> Result<(Vec<Certificate>, PrivateKey)> {
+        let certs = read_cert(&self.cert_path)
+            .with_context(|| format!("Failed to read certificate {}", self.cert_path.display()))?;
+        if certs.is_empty() {
+            anyhow::bail!("No certificate found in {}", self.cert_path.display());
+        }
+
+        let key = read_key(&self.key_path)
+            .with_context(|| format!("Failed to read private key {}", self.key_path.display()))?;
+
+        Ok((certs, key))
+    }
+}
+
+/// Hands the current certificate to each TLS handshake. Replacing the certificate
+/// takes effect for new connections without restarting the listeners.
+pub struct CertificateStore {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateStore {
+    pub fn new(certificate: &(Vec<Certificate>, PrivateKey)) -> Result<Self> {
+        Ok(Self {
+            current: RwLock::new(certified_key(certificate)?),
+        })
+    }
+
+    /// Serves this certificate from now on. Fails, keeping the current one, if the key is unusable.
+    pub fn replace(&self, certificate: &(Vec<Certificate>, PrivateKey)) -> Result<()> {
+        let key = certified_key(certificate)?;
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = key;
+        Ok(())
+    }
+
+    /// A server config presenting the certificates of this store
+    pub fn server_config(self: &Arc<Self>) -> Arc<ServerConfig> {
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::clone(self) as Arc<dyn ResolvesServerCert>);
+        Arc::new(config)
+    }
+
+    fn current(&self) -> Arc<CertifiedKey> {
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl ResolvesServerCert for CertificateStore {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+fn certified_key((certs, key): &(Vec<Certificate>, PrivateKey)) -> Result<Arc<CertifiedKey>> {
+    let key = any_supported_type(key).map_err(|_| anyhow::anyhow!("Unsupported private key type"))?;
+    Ok(Arc::new(CertifiedKey::new(certs.clone(), key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(cert: &str, key: &str) -> TlsConfig {
+        let data = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        TlsConfig {
+            cert_path: data.join(cert),
+            key_path: data.join(key),
+            dot_addr: None,
+            doh_addr: None,
+            doh_hostname: None,
+        }
+    }
+
+    #[test]
+    fn loads_pem_certificate_and_key() {
+        let (certs, _key) = test_config("test-cert.pem", "test-key.pem").load_certificate().unwrap();
+        assert_eq!(certs.len(), 1);
+    }
+
+    #[test]
+    fn reports_missing_or_invalid_files() {
+        assert!(test_config("missing.pem", "test-key.pem").load_certificate().is_err());
+        assert!(test_config("test-key.pem", "test-key.pem").load_certificate().is_err());
+        assert!(test_config("test-cert.pem", "test-cert.pem").load_certificate().is_err());
+    }
+
+    #[test]
+    fn replaces_certificate_in_place() {
+        let certificate = test_config("test-cert.pem", "test-key.pem").load_certificate().unwrap();
+        let store = CertificateStore::new(&certificate).unwrap();
+        let before = store.current();
+
+        store.replace(&certificate).unwrap();
+        assert!(!Arc::ptr_eq(&before, &store.current()));
+
+        let unusable = (certificate.0.clone(), PrivateKey(vec![1, 2, 3]));
+        let current = store.current();
+        assert!(store.replace(&unusable).is_err());
+        assert!(Arc::ptr_eq(&current, &store.current()));
+    }
+}
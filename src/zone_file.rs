@@ -0,0 +1,194 @@
+use crate::file_watch::watch_file;
+use anyhow::{Context, Result};
+use hickory_server::proto::rr::{Name, Record, RecordType};
+use hickory_server::proto::serialize::txt::Parser;
+use log::{error, info};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Records by lowercase name without the trailing dot
+type Records = HashMap<String, Vec<Record>>;
+
+/// Hand-written records (SRV, TXT, MX, CNAME, ...) from an RFC 1035 master file,
+/// reloaded when the file changes
+#[derive(Debug)]
+pub struct ZoneFile {
+    path: PathBuf,
+    origin: Name,
+    records: RwLock<Arc<Records>>,
+}
+
+impl ZoneFile {
+    /// Reads the file. Relative names are relative to `origin` (usually the suffix), unless the file sets `$ORIGIN`.
+    pub fn load(path: impl Into<PathBuf>, origin: &str) -> Result<Self> {
+        let path = path.into();
+        let origin = match origin.trim_matches('.') {
+            "" => Name::root(),
+            origin => Name::from_ascii(format!("{}.", origin)).with_context(|| format!("Invalid zone origin '{}'", origin))?,
+        };
+        let records = read_zone_file(&path, &origin)?;
+        Ok(Self {
+            path,
+            origin,
+            records: RwLock::new(Arc::new(records)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of records currently loaded
+    pub fn len(&self) -> usize {
+        self.current().values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current().is_empty()
+    }
+
+    /// All records of a name (lowercase, without the trailing dot)
+    pub fn lookup(&self, name: &str) -> Option<Vec<Record>> {
+        self.current().get(name).cloned()
+    }
+
+    /// Reads the file again. On error the current records are kept.
+    pub fn reload(&self) -> Result<()> {
+        let records = read_zone_file(&self.path, &self.origin)?;
+        *self.records.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(records);
+        Ok(())
+    }
+
+    /// Reloads the file whenever it is written or replaced
+    pub async fn watch(self: Arc<Self>) -> Result<()> {
+        watch_file(&self.path, || match self.reload() {
+            Ok(()) => info!("Reloaded {}: {} records", self.path.display(), self.len()),
+            Err(e) => error!("Keeping the previous zone records: {:#}", e),
+        })
+        .await
+    }
+
+    fn current(&self) -> Arc<Records> {
+        Arc::clone(&self.records.read().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+fn read_zone_file(path: &Path, origin: &Name) -> Result<Records> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let records = parse_records(&content, path, origin).with_context(|| format!("Invalid zone file {}", path.display()))?;
+
+    let suffix = origin.to_lowercase().to_string();
+    let suffix = suffix.trim_end_matches('.');
+    let mut names = records.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        let name_records = &records[name];
+        let has_cname = name_records.iter().any(|record| record.record_type() == RecordType::CNAME);
+        if has_cname && name_records.len() > 1 {
+            let line = line_where(&content, path, origin, |records| records.get(name).is_some_and(|records| records.len() > 1));
+            anyhow::bail!(
+                "{}:{}: '{}' has a CNAME and other records; a CNAME must be the only record of its name (RFC 1034 section 3.6.2)",
+                path.display(),
+                line.unwrap_or_default(),
+                name
+            );
+        }
+        if !suffix.is_empty() && !name.ends_with(&format!(".{}", suffix)) {
+            let line = line_where(&content, path, origin, |records| records.contains_key(name));
+            anyhow::bail!(
+                "{}:{}: '{}' is not under the suffix '{}' and would never be answered",
+                path.display(),
+                line.unwrap_or_default(),
+                name,
+                suffix
+            );
+        }
+    }
+    Ok(records)
+}
+
+fn parse_records(content: &str, path: &Path, origin: &Name) -> Result<Records> {
+    // The path is for $INCLUDE
+    let (_, record_sets) = Parser::new(content, Some(path.to_path_buf()), Some(origin.clone())).parse()?;
+
+    let mut records = Records::new();
+    for record_set in record_sets.values() {
+        let name = record_set.name().to_lowercase().to_string();
+        records
+            .entry(name.trim_end_matches('.').to_string())
+            .or_default()
+            .extend(record_set.records_without_rrsigs().cloned());
+    }
+    Ok(records)
+}
+
+/// The first line by which the records read so far match `found`, for error messages.
+/// The parser doesn't report lines, so growing prefixes of the file are parsed.
+fn line_where(content: &str, path: &Path, origin: &Name, found: impl Fn(&Records) -> bool) -> Option<usize> {
+    let lines = content.lines().collect::<Vec<_>>();
+    (1..=lines.len()).find(|&count| parse_records(&lines[..count].join("\n"), path, origin).is_ok_and(|records| found(&records)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    const ZONE: &str = "\
+$TTL 300
+_ldap._tcp  IN SRV   10 5 389 ldap
+ldap        IN TXT   \"v=1\"
+www         IN CNAME web
+Mail.Docker. IN MX 10 mx.example.com.
+";
+
+    #[test]
+    fn loads_records_relative_to_origin() {
+        let dir = TempDir::new("zone-load");
+        let zone = ZoneFile::load(dir.write("docker.zone", ZONE), ".docker").unwrap();
+
+        let srv = zone.lookup("_ldap._tcp.docker").unwrap();
+        assert_eq!(srv.len(), 1);
+        assert_eq!(srv[0].record_type(), RecordType::SRV);
+        assert_eq!(srv[0].ttl(), 300);
+        assert_eq!(zone.lookup("www.docker").unwrap()[0].record_type(), RecordType::CNAME);
+        assert_eq!(zone.lookup("mail.docker").unwrap()[0].record_type(), RecordType::MX);
+        assert_eq!(zone.lookup("ldap"), None);
+        assert_eq!(zone.len(), 4);
+    }
+
+    #[test]
+    fn rejects_cname_with_other_records() {
+        let dir = TempDir::new("zone-cname-data");
+        let path = dir.write("docker.zone", "$TTL 300\nwww IN CNAME web\nldap IN TXT \"v=1\"\nwww IN TXT \"v=1\"\n");
+
+        let error = format!("{:#}", ZoneFile::load(&path, "docker").unwrap_err());
+
+        assert!(error.contains("docker.zone:4: 'www.docker' has a CNAME and other records"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_names_outside_the_suffix() {
+        let dir = TempDir::new("zone-outside");
+        let path = dir.write("docker.zone", "$TTL 300\nldap IN TXT \"v=1\"\nmail.example.com. IN MX 10 mx\n");
+
+        let error = format!("{:#}", ZoneFile::load(&path, "docker").unwrap_err());
+        assert!(error.contains("docker.zone:3: 'mail.example.com' is not under the suffix 'docker'"), "{}", error);
+
+        // Without a suffix every name is answered
+        assert!(ZoneFile::load(&path, "").is_ok());
+    }
+
+    #[test]
+    fn keeps_previous_records_on_bad_reload() {
+        let dir = TempDir::new("zone-reload");
+        let path = dir.write("docker.zone", ZONE);
+        let zone = ZoneFile::load(&path, "docker").unwrap();
+
+        std::fs::write(&path, "ldap IN TXT\nbroken IN SRV 10\n").unwrap();
+
+        assert!(zone.reload().is_err());
+        assert!(zone.lookup("_ldap._tcp.docker").is_some());
+    }
+}
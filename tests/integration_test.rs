@@ -1,6 +1,12 @@
-use docker_dns::resolver::StaticResolver;
-use docker_dns::server::DnsServer;
+use docker_dns::access_control::AccessControl;
+use docker_dns::bind::BindAddress;
+use docker_dns::custom_handler::CustomHandlerConfig;
+use async_trait::async_trait;
+use docker_dns::resolver::{DnsResolver, DnsResponse, StaticResolver};
+use docker_dns::server::{DnsServer, ServerHandle};
+use docker_dns::systemd::ActivatedSocket;
 use hickory_client::client::{AsyncClient, ClientHandle};
+use hickory_client::op::ResponseCode;
 use hickory_client::rr::{DNSClass, Name, RecordType};
 use hickory_client::udp::UdpClientStream;
 use std::net::{Ipv4Addr, SocketAddr};
@@ -8,25 +14,36 @@ use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
-#[tokio::test]
-async fn test_resolves_my_example_local_to_static_ip() {
-    let server_addr: SocketAddr = "127.0.0.1:5353".parse().unwrap();
+/// Starts the server and returns it with the IPv4 address it got
+async fn start(server: DnsServer) -> (ServerHandle, SocketAddr) {
+    let server = server.start();
+    server.ready().await.unwrap();
+    let addr = server.local_addrs().into_iter().find(SocketAddr::is_ipv4).unwrap();
+    (server, addr)
+}
 
-    let mut resolver = StaticResolver::new();
-    resolver.add_mapping("my.example.local", Ipv4Addr::new(10, 11, 12, 13));
+async fn connect(server_addr: SocketAddr) -> AsyncClient {
+    let stream = UdpClientStream::<tokio::net::UdpSocket>::new(server_addr);
+    let (client, bg) = AsyncClient::connect(stream).await.unwrap();
 
-    let server = DnsServer::new(Arc::new(resolver), server_addr, "".to_string(), 60);
+    tokio::spawn(bg);
 
-    tokio::spawn(async move {
-        server.run().await.unwrap();
-    });
+    client
+}
 
-    tokio::time::sleep(Duration::from_millis(100)).await;
+fn any_port() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
 
-    let stream = UdpClientStream::<tokio::net::UdpSocket>::new(server_addr);
-    let (mut client, bg) = AsyncClient::connect(stream).await.unwrap();
+#[tokio::test]
+async fn test_resolves_my_example_local_to_static_ip() {
+    let mut resolver = StaticResolver::new();
+    resolver.add_mapping("my.example.local", Ipv4Addr::new(10, 11, 12, 13));
 
-    tokio::spawn(bg);
+    let server = DnsServer::new(Arc::new(resolver), any_port(), CustomHandlerConfig::default());
+    let (_server, server_addr) = start(server).await;
+
+    let mut client = connect(server_addr).await;
 
     let name = Name::from_str("my.example.local").unwrap();
     let response = client.query(name, DNSClass::IN, RecordType::A).await.unwrap();
@@ -39,3 +56,219 @@ async fn test_resolves_my_example_local_to_static_ip() {
 
     assert_eq!(ip.0, Ipv4Addr::new(10, 11, 12, 13));
 }
+
+#[tokio::test]
+async fn test_matches_case_insensitively_and_preserves_query_case() {
+    let mut resolver = StaticResolver::new();
+    resolver.add_mapping("myapp", Ipv4Addr::new(10, 11, 12, 13));
+
+    let config = CustomHandlerConfig {
+        suffix: ".docker".to_string(),
+        ..Default::default()
+    };
+    let server = DnsServer::new(Arc::new(resolver), any_port(), config);
+    let (_server, server_addr) = start(server).await;
+
+    let mut client = connect(server_addr).await;
+
+    let name = Name::from_ascii("MyApp.DoCkEr").unwrap();
+    let response = client.query(name, DNSClass::IN, RecordType::A).await.unwrap();
+
+    let answers = response.answers();
+    assert_eq!(answers.len(), 1, "Expected exactly one answer");
+
+    let record = &answers[0];
+    assert_eq!(record.name().to_string(), "MyApp.DoCkEr.");
+    assert_eq!(record.data().unwrap().as_a().unwrap().0, Ipv4Addr::new(10, 11, 12, 13));
+}
+
+#[tokio::test]
+async fn test_refuses_denied_clients() {
+    let mut resolver = StaticResolver::new();
+    resolver.add_mapping("my.example.local", Ipv4Addr::new(10, 11, 12, 13));
+
+    let config = CustomHandlerConfig {
+        access_control: AccessControl {
+            deny: vec!["127.0.0.0/8".parse().unwrap()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let server = DnsServer::new(Arc::new(resolver), any_port(), config);
+    let (_server, server_addr) = start(server).await;
+
+    let mut client = connect(server_addr).await;
+
+    let name = Name::from_str("my.example.local").unwrap();
+    let response = client.query(name, DNSClass::IN, RecordType::A).await.unwrap();
+
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+    assert!(response.answers().is_empty());
+}
+
+#[tokio::test]
+async fn test_listens_on_interface_addresses() {
+    let mut resolver = StaticResolver::new();
+    resolver.add_mapping("my.example.local", Ipv4Addr::new(10, 11, 12, 13));
+
+    let binds = vec!["lo:0".parse::<BindAddress>().unwrap()];
+    let server = DnsServer::new_with_binds(Arc::new(resolver), binds, CustomHandlerConfig::default());
+    let (_server, server_addr) = start(server).await;
+    assert!(server_addr.ip().is_loopback());
+
+    let mut client = connect(server_addr).await;
+
+    let name = Name::from_str("my.example.local").unwrap();
+    let response = client.query(name, DNSClass::IN, RecordType::A).await.unwrap();
+
+    assert_eq!(response.answers().len(), 1);
+}
+
+#[tokio::test]
+async fn test_serves_from_several_udp_sockets() {
+    let mut resolver = StaticResolver::new();
+    resolver.add_mapping("my.example.local", Ipv4Addr::new(10, 11, 12, 13));
+
+    let server =
+        DnsServer::new(Arc::new(resolver), any_port(), CustomHandlerConfig::default()).with_udp_sockets(4);
+    let (_server, server_addr) = start(server).await;
+
+    // Each client uses its own source port, so the kernel spreads them over the sockets
+    for _ in 0..8 {
+        let mut client = connect(server_addr).await;
+
+        let name = Name::from_str("my.example.local").unwrap();
+        let response = client.query(name, DNSClass::IN, RecordType::A).await.unwrap();
+        assert_eq!(response.answers().len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_serves_on_activated_sockets() {
+    // Sockets as systemd would pass them in
+    let socket = std::net::UdpSocket::bind(any_port()).unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let server_addr = socket.local_addr().unwrap();
+    let listener = std::net::TcpListener::bind(server_addr).unwrap();
+    listener.set_nonblocking(true).unwrap();
+
+    let mut resolver = StaticResolver::new();
+    resolver.add_mapping("my.example.local", Ipv4Addr::new(10, 11, 12, 13));
+
+    let server = DnsServer::new_with_binds(Arc::new(resolver), vec![], CustomHandlerConfig::default())
+        .with_activated_sockets(vec![ActivatedSocket::Udp(socket), ActivatedSocket::Tcp(listener)]);
+    let (server, _) = start(server).await;
+    assert_eq!(server.local_addrs(), vec![server_addr]);
+
+    let mut client = connect(server_addr).await;
+
+    let name = Name::from_str("my.example.local").unwrap();
+    let response = client.query(name, DNSClass::IN, RecordType::A).await.unwrap();
+
+    assert_eq!(response.answers().len(), 1);
+}
+
+#[tokio::test]
+async fn test_shuts_down_gracefully() {
+    let mut resolver = StaticResolver::new();
+    resolver.add_mapping("my.example.local", Ipv4Addr::new(10, 11, 12, 13));
+
+    let server = DnsServer::new(Arc::new(resolver), any_port(), CustomHandlerConfig::default())
+        .with_shutdown_timeout(Duration::from_secs(2));
+    let (server, server_addr) = start(server).await;
+
+    let mut client = connect(server_addr).await;
+
+    let name = Name::from_str("my.example.local").unwrap();
+    let response = client.query(name, DNSClass::IN, RecordType::A).await.unwrap();
+    assert_eq!(response.answers().len(), 1);
+
+    let result = tokio::time::timeout(Duration::from_secs(3), server.shutdown()).await;
+    assert!(result.unwrap().is_ok());
+
+    // The addresses are free again
+    std::net::UdpSocket::bind(server_addr).unwrap();
+    std::net::TcpListener::bind(server_addr).unwrap();
+}
+
+/// Answers "slow" after a delay, other names like the wrapped resolver
+struct SlowResolver(StaticResolver);
+
+#[async_trait]
+impl DnsResolver for SlowResolver {
+    async fn resolve(&self, domain: &str) -> Option<Arc<DnsResponse>> {
+        if domain == "slow" {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        self.0.resolve(domain).await
+    }
+}
+
+#[tokio::test]
+async fn test_refuses_queries_while_shutting_down() {
+    let mut resolver = StaticResolver::new();
+    resolver.add_mapping("slow", Ipv4Addr::new(10, 0, 0, 1));
+    resolver.add_mapping("fast", Ipv4Addr::new(10, 0, 0, 2));
+
+    let server = DnsServer::new(Arc::new(SlowResolver(resolver)), any_port(), CustomHandlerConfig::default())
+        .with_shutdown_timeout(Duration::from_secs(2));
+    let (server, server_addr) = start(server).await;
+
+    let mut client = connect(server_addr).await;
+    let mut slow_client = client.clone();
+    let slow = tokio::spawn(async move {
+        slow_client.query(Name::from_str("slow").unwrap(), DNSClass::IN, RecordType::A).await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let shutdown = tokio::spawn(server.shutdown());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Draining: the query in progress is answered, new ones are refused
+    let response = client.query(Name::from_str("fast").unwrap(), DNSClass::IN, RecordType::A).await.unwrap();
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+    assert_eq!(slow.await.unwrap().unwrap().answers().len(), 1);
+    assert!(shutdown.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn test_reports_startup_failure() {
+    let taken = std::net::UdpSocket::bind(any_port()).unwrap();
+
+    let server = DnsServer::new(
+        Arc::new(StaticResolver::new()),
+        taken.local_addr().unwrap(),
+        CustomHandlerConfig::default(),
+    )
+    .start();
+
+    assert!(server.ready().await.is_err());
+    assert!(server.wait().await.is_err());
+}
+
+#[tokio::test]
+async fn test_swaps_resolver_and_suffix_while_serving() {
+    let mut resolver = StaticResolver::new();
+    resolver.add_mapping("myapp", Ipv4Addr::new(10, 11, 12, 13));
+
+    let config = CustomHandlerConfig {
+        suffix: ".docker".to_string(),
+        ..Default::default()
+    };
+    let server = DnsServer::new(Arc::new(resolver), any_port(), config);
+    let (server, server_addr) = start(server).await;
+
+    let mut replacement = StaticResolver::new();
+    replacement.add_mapping("myapp", Ipv4Addr::new(10, 0, 0, 2));
+    server.set_resolver(Arc::new(replacement));
+    server.set_suffix(".local");
+
+    let mut client = connect(server_addr).await;
+
+    let name = Name::from_str("myapp.local").unwrap();
+    let response = client.query(name, DNSClass::IN, RecordType::A).await.unwrap();
+    assert_eq!(response.answers()[0].data().unwrap().as_a().unwrap().0, Ipv4Addr::new(10, 0, 0, 2));
+
+    let name = Name::from_str("myapp.docker").unwrap();
+    let response = client.query(name, DNSClass::IN, RecordType::A).await.unwrap();
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+}